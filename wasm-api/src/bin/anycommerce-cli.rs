@@ -0,0 +1,267 @@
+//! Native front end to the JSON API layer, for scripting and debugging.
+//!
+//! ```text
+//! anycommerce-cli sku <product.json> [id=value...]
+//! anycommerce-cli price <product.json> [id=value...]
+//! anycommerce-cli inventory <product.json> <sku>
+//! anycommerce-cli cart <cart.json>
+//! anycommerce-cli validate <requests.jsonl|->
+//! ```
+
+use std::collections::HashMap;
+use std::io::Read;
+use std::process::ExitCode;
+
+use anycommerce_wasm::{commands, ApiRequest, Cart, Product};
+use serde_json::Value;
+
+const USAGE: &str = "usage:
+  anycommerce-cli sku <product.json> [id=value...]
+  anycommerce-cli price <product.json> [id=value...]
+  anycommerce-cli inventory <product.json> <sku>
+  anycommerce-cli cart <cart.json>
+  anycommerce-cli validate <requests.jsonl|->";
+
+/// A subcommand and its arguments
+#[derive(Debug, PartialEq)]
+enum Command<'a> {
+    Sku(&'a str, &'a [String]),
+    Price(&'a str, &'a [String]),
+    Inventory(&'a str, &'a str),
+    Cart(&'a str),
+    Validate(&'a str),
+    Help,
+}
+
+fn main() -> ExitCode {
+    let args: Vec<String> = std::env::args().skip(1).collect();
+    let command = match parse_args(&args) {
+        Some(Command::Help) => {
+            println!("{}", USAGE);
+            return ExitCode::SUCCESS;
+        }
+        Some(command) => command,
+        None => {
+            eprintln!("{}", USAGE);
+            return ExitCode::from(2);
+        }
+    };
+
+    match run(command) {
+        Ok(true) => ExitCode::SUCCESS,
+        Ok(false) => ExitCode::FAILURE,
+        Err(e) => {
+            eprintln!("error: {}", e);
+            ExitCode::FAILURE
+        }
+    }
+}
+
+/// The subcommand named by the arguments, or None if they don't match the usage
+fn parse_args(args: &[String]) -> Option<Command<'_>> {
+    let (command, args) = args.split_first()?;
+
+    Some(match (command.as_str(), args) {
+        ("sku", [path, selections @ ..]) => Command::Sku(path, selections),
+        ("price", [path, selections @ ..]) => Command::Price(path, selections),
+        ("inventory", [path, sku]) => Command::Inventory(path, sku),
+        ("cart", [path]) => Command::Cart(path),
+        ("validate", [path]) => Command::Validate(path),
+        ("help" | "-h" | "--help", _) => Command::Help,
+        _ => return None,
+    })
+}
+
+/// Run a subcommand. Returns false if it found problems.
+fn run(command: Command) -> Result<bool, String> {
+    let output = match command {
+        Command::Sku(path, selections) => sku(path, selections)?,
+        Command::Price(path, selections) => price(path, selections)?,
+        Command::Inventory(path, sku) => inventory(path, sku)?,
+        Command::Cart(path) => cart(path)?,
+        Command::Validate(path) => return validate(path),
+        Command::Help => USAGE.to_string(),
+    };
+    println!("{}", output);
+
+    Ok(true)
+}
+
+fn sku(path: &str, selections: &[String]) -> Result<String, String> {
+    let product = load_product(path)?;
+
+    product
+        .sku(&parse_selections(selections)?)
+        .map_err(|e| e.to_string())
+}
+
+fn price(path: &str, selections: &[String]) -> Result<String, String> {
+    let product = load_product(path)?;
+
+    Ok(format!(
+        "{:.2}",
+        product.price(&parse_selections(selections)?)
+    ))
+}
+
+fn inventory(path: &str, sku: &str) -> Result<String, String> {
+    let product = load_product(path)?;
+    let item = product
+        .inventory
+        .get(sku)
+        .ok_or_else(|| format!("{} has no inventory record for {}", product.pid, sku))?;
+
+    to_json(item)
+}
+
+/// The cart with its totals recalculated from the items
+fn cart(path: &str) -> Result<String, String> {
+    let mut value = read_json(path)?;
+    // An order or cart record from the API nests the ID under `cart`
+    let cartid = value.pointer("/cart/cartid").cloned();
+    if let (Some(map), Some(cartid)) = (value.as_object_mut(), cartid) {
+        map.entry("cart_id").or_insert(cartid);
+    }
+
+    let mut cart: Cart = serde_json::from_value(value)
+        .map_err(|e| format!("Failed to parse cart {}: {}", path, e))?;
+    cart.recalculate();
+
+    to_json(&cart)
+}
+
+/// Check each line of a JSONL file: either a request or a recorded exchange.
+/// Returns false if any line is invalid.
+fn validate(path: &str) -> Result<bool, String> {
+    let text = read_text(path)?;
+
+    let mut valid = true;
+    for (i, line) in text.lines().enumerate() {
+        if line.trim().is_empty() {
+            continue;
+        }
+        let result = serde_json::from_str::<Value>(line)
+            .map_err(|e| e.to_string())
+            .and_then(|mut value| {
+                // Recorder logs wrap the request in an exchange
+                if let Some(request) = value.get_mut("request") {
+                    value = request.take();
+                }
+                serde_json::from_value::<ApiRequest>(value).map_err(|e| e.to_string())
+            })
+            .and_then(|request| {
+                commands::validate_request(&request)
+                    .map(|_| request.cmd)
+                    .map_err(|e| e.to_string())
+            });
+
+        match result {
+            Ok(cmd) => println!("{}: ok {}", i + 1, cmd),
+            Err(e) => {
+                println!("{}: invalid: {}", i + 1, e);
+                valid = false;
+            }
+        }
+    }
+
+    Ok(valid)
+}
+
+fn parse_selections(args: &[String]) -> Result<HashMap<String, String>, String> {
+    args.iter()
+        .map(|arg| {
+            arg.split_once('=')
+                .map(|(id, value)| (id.to_string(), value.to_string()))
+                .ok_or_else(|| format!("Expected id=value, got {}", arg))
+        })
+        .collect()
+}
+
+fn load_product(path: &str) -> Result<Product, String> {
+    serde_json::from_value(read_json(path)?)
+        .map_err(|e| format!("Failed to parse product {}: {}", path, e))
+}
+
+fn read_json(path: &str) -> Result<Value, String> {
+    serde_json::from_str(&read_text(path)?).map_err(|e| format!("Failed to parse {}: {}", path, e))
+}
+
+/// Read a file, or stdin for `-`
+fn read_text(path: &str) -> Result<String, String> {
+    if path == "-" {
+        let mut text = String::new();
+        std::io::stdin()
+            .read_to_string(&mut text)
+            .map_err(|e| format!("Failed to read stdin: {}", e))?;
+        return Ok(text);
+    }
+
+    std::fs::read_to_string(path).map_err(|e| format!("Failed to read {}: {}", path, e))
+}
+
+fn to_json(value: &impl serde::Serialize) -> Result<String, String> {
+    serde_json::to_string_pretty(value).map_err(|e| format!("Failed to serialize: {}", e))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn sample(name: &str) -> String {
+        format!(
+            "{}/../legacy/examples/sample-data/{}",
+            env!("CARGO_MANIFEST_DIR"),
+            name
+        )
+    }
+
+    fn args(args: &[&str]) -> Vec<String> {
+        args.iter().map(|arg| arg.to_string()).collect()
+    }
+
+    #[test]
+    fn test_parse_args() {
+        let sku_args = args(&["sku", "product.json", "02=09"]);
+        assert_eq!(
+            parse_args(&sku_args),
+            Some(Command::Sku("product.json", &sku_args[2..]))
+        );
+        let inventory_args = args(&["inventory", "product.json", "TEST:0209"]);
+        assert_eq!(
+            parse_args(&inventory_args),
+            Some(Command::Inventory("product.json", "TEST:0209"))
+        );
+        assert_eq!(parse_args(&args(&["--help"])), Some(Command::Help));
+
+        assert_eq!(parse_args(&[]), None);
+        assert_eq!(parse_args(&args(&["sku"])), None);
+        assert_eq!(parse_args(&args(&["cart", "a.json", "b.json"])), None);
+        assert_eq!(parse_args(&args(&["ship", "order.json"])), None);
+
+        assert_eq!(
+            parse_selections(&args(&["02=09", "A1=ON"])),
+            Ok(HashMap::from([
+                ("02".to_string(), "09".to_string()),
+                ("A1".to_string(), "ON".to_string()),
+            ]))
+        );
+        assert!(parse_selections(&args(&["0209"])).is_err());
+    }
+
+    #[test]
+    fn test_sample_data() {
+        let product = sample("product.json");
+        for size in ["09", "00", "07", "01"] {
+            let sku = sku(&product, &args(&[&format!("02={}", size)])).unwrap();
+            assert_eq!(sku, format!("TEST:02{}", size));
+            assert!(inventory(&product, &sku).is_ok(), "{}", sku);
+        }
+        assert!(sku(&product, &[]).is_err());
+
+        let cart: Value = serde_json::from_str(&cart(&sample("order.json")).unwrap()).unwrap();
+        assert_eq!(cart["cart_id"], "YKxCOg9jNE1NVfinnr1SfcScC");
+        assert_eq!(cart["@ITEMS"][0]["sku"], "BLUE");
+        assert_eq!(cart["@ITEMS"][0]["qty"], 1);
+        assert_eq!(cart["sum"]["balance_due"], 12.99);
+    }
+}
@@ -1,29 +1,46 @@
 use wasm_bindgen::prelude::*;
 use serde::{Deserialize, Serialize};
 use std::collections::HashMap;
+use thiserror::Error;
+
+use crate::utils::number;
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct CartItem {
     pub sku: String,
+    #[serde(alias = "product")]
     pub pid: String,
+    #[serde(default)]
     pub prod_name: String,
+    #[serde(deserialize_with = "number::deserialize")]
     pub qty: u32,
+    #[serde(default, deserialize_with = "number::deserialize")]
     pub base_price: f64,
+    #[serde(deserialize_with = "number::deserialize")]
     pub price: f64,
     #[serde(skip_serializing_if = "Option::is_none")]
     pub variations: Option<HashMap<String, String>>,
 }
 
-#[derive(Debug, Clone, Serialize, Deserialize)]
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
 pub struct CartSummary {
+    #[serde(default, deserialize_with = "number::deserialize")]
     pub items_total: f64,
+    #[serde(default, alias = "shp_total", deserialize_with = "number::deserialize")]
     pub shipping_total: f64,
+    #[serde(default, deserialize_with = "number::deserialize")]
     pub tax_total: f64,
+    #[serde(default, deserialize_with = "number::deserialize")]
     pub discount_total: f64,
+    #[serde(
+        default,
+        alias = "balance_due_total",
+        deserialize_with = "number::deserialize"
+    )]
     pub balance_due: f64,
 }
 
-#[derive(Debug, Clone, Serialize, Deserialize)]
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
 pub struct CheckoutPreferences {
     #[serde(skip_serializing_if = "Option::is_none")]
     pub shipping_id: Option<String>,
@@ -33,16 +50,114 @@ pub struct CheckoutPreferences {
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct Cart {
+    #[serde(default)]
     pub cart_id: String,
     #[serde(rename = "@ITEMS")]
     pub items: Vec<CartItem>,
+    #[serde(default)]
     pub sum: CartSummary,
+    #[serde(default)]
     pub want: CheckoutPreferences,
     #[serde(default)]
     pub coupons: Vec<String>,
 }
 
+/// Why a cart operation failed
+#[derive(Debug, Clone, PartialEq, Eq, Error)]
+pub enum CartError {
+    #[error("Cart {0} not found")]
+    NotFound(String),
+    #[error("Item {0} not found in cart")]
+    ItemNotFound(String),
+}
+
+impl From<CartError> for JsValue {
+    fn from(error: CartError) -> Self {
+        JsValue::from_str(&error.to_string())
+    }
+}
+
+impl Cart {
+    pub fn new(cart_id: String) -> Cart {
+        Cart {
+            cart_id,
+            items: vec![],
+            sum: CartSummary::default(),
+            want: CheckoutPreferences::default(),
+            coupons: vec![],
+        }
+    }
+
+    /// Add an item, or add to the quantity of the item with the same SKU
+    pub fn add_item(&mut self, item: CartItem) {
+        if let Some(existing) = self.items.iter_mut().find(|i| i.sku == item.sku) {
+            existing.qty += item.qty;
+        } else {
+            self.items.push(item);
+        }
+
+        self.recalculate();
+    }
+
+    /// Set an item's quantity. A quantity of 0 removes the item.
+    pub fn update_item(&mut self, sku: &str, qty: u32) -> Result<(), CartError> {
+        let item = self
+            .items
+            .iter_mut()
+            .find(|i| i.sku == sku)
+            .ok_or_else(|| CartError::ItemNotFound(sku.to_string()))?;
+
+        if qty == 0 {
+            self.items.retain(|i| i.sku != sku);
+        } else {
+            item.qty = qty;
+        }
+
+        self.recalculate();
+        Ok(())
+    }
+
+    pub fn remove_item(&mut self, sku: &str) {
+        self.items.retain(|i| i.sku != sku);
+        self.recalculate();
+    }
+
+    /// Track a coupon code. Coupon discounts are calculated server-side.
+    pub fn add_coupon(&mut self, coupon: String) {
+        if !self.coupons.contains(&coupon) {
+            self.coupons.push(coupon);
+        }
+    }
+
+    /// Remove all items, coupons and totals
+    pub fn clear(&mut self) {
+        self.items.clear();
+        self.coupons.clear();
+        self.sum = CartSummary::default();
+    }
+
+    /// Recompute the item and balance totals from the items.
+    /// Shipping, tax and discounts come from the server and are kept.
+    pub fn recalculate(&mut self) {
+        let items_total: f64 = self
+            .items
+            .iter()
+            .map(|item| item.price * item.qty as f64)
+            .sum();
+
+        self.sum.items_total = items_total;
+        self.sum.balance_due =
+            items_total + self.sum.shipping_total + self.sum.tax_total - self.sum.discount_total;
+    }
+
+    /// Total quantity of all items
+    pub fn item_count(&self) -> u32 {
+        self.items.iter().map(|item| item.qty).sum()
+    }
+}
+
 #[wasm_bindgen]
+#[derive(Default)]
 pub struct CartManager {
     carts: HashMap<String, Cart>,
 }
@@ -51,34 +166,14 @@ pub struct CartManager {
 impl CartManager {
     #[wasm_bindgen(constructor)]
     pub fn new() -> CartManager {
-        CartManager {
-            carts: HashMap::new(),
-        }
+        CartManager::default()
     }
 
     /// Create a new cart
     pub fn create_cart(&mut self, cart_id: String) -> Result<JsValue, JsValue> {
-        let cart = Cart {
-            cart_id: cart_id.clone(),
-            items: vec![],
-            sum: CartSummary {
-                items_total: 0.0,
-                shipping_total: 0.0,
-                tax_total: 0.0,
-                discount_total: 0.0,
-                balance_due: 0.0,
-            },
-            want: CheckoutPreferences {
-                shipping_id: None,
-                payby: None,
-            },
-            coupons: vec![],
-        };
+        let cart = self.create(cart_id);
 
-        self.carts.insert(cart_id.clone(), cart.clone());
-
-        serde_wasm_bindgen::to_value(&cart)
-            .map_err(|e| JsValue::from_str(&format!("Failed to serialize cart: {}", e)))
+        to_js(cart)
     }
 
     /// Load a cart from JSON (from API response)
@@ -86,148 +181,105 @@ impl CartManager {
         let cart: Cart = serde_wasm_bindgen::from_value(cart_json)
             .map_err(|e| JsValue::from_str(&format!("Failed to parse cart: {}", e)))?;
 
-        let cart_id = cart.cart_id.clone();
-        self.carts.insert(cart_id.clone(), cart);
-
-        Ok(cart_id)
+        Ok(self.insert(cart))
     }
 
     /// Get a cart by ID
     pub fn get_cart(&self, cart_id: &str) -> Result<JsValue, JsValue> {
-        let cart = self
-            .carts
-            .get(cart_id)
-            .ok_or_else(|| JsValue::from_str(&format!("Cart {} not found", cart_id)))?;
-
-        serde_wasm_bindgen::to_value(cart)
-            .map_err(|e| JsValue::from_str(&format!("Failed to serialize cart: {}", e)))
+        to_js(self.cart(cart_id)?)
     }
 
     /// Add an item to the cart
     pub fn add_item(&mut self, cart_id: &str, item: JsValue) -> Result<JsValue, JsValue> {
-        let cart = self
-            .carts
-            .get_mut(cart_id)
-            .ok_or_else(|| JsValue::from_str(&format!("Cart {} not found", cart_id)))?;
-
         let item: CartItem = serde_wasm_bindgen::from_value(item)
             .map_err(|e| JsValue::from_str(&format!("Failed to parse item: {}", e)))?;
 
-        // Check if item already exists (same SKU)
-        if let Some(existing) = cart.items.iter_mut().find(|i| i.sku == item.sku) {
-            existing.qty += item.qty;
-        } else {
-            cart.items.push(item);
-        }
-
-        self.recalculate_totals(cart_id)?;
+        let cart = self.cart_mut(cart_id)?;
+        cart.add_item(item);
 
-        self.get_cart(cart_id)
+        to_js(cart)
     }
 
     /// Update item quantity
     pub fn update_item(&mut self, cart_id: &str, sku: &str, qty: u32) -> Result<JsValue, JsValue> {
-        let cart = self
-            .carts
-            .get_mut(cart_id)
-            .ok_or_else(|| JsValue::from_str(&format!("Cart {} not found", cart_id)))?;
-
-        let item = cart
-            .items
-            .iter_mut()
-            .find(|i| i.sku == sku)
-            .ok_or_else(|| JsValue::from_str(&format!("Item {} not found in cart", sku)))?;
+        let cart = self.cart_mut(cart_id)?;
+        cart.update_item(sku, qty)?;
 
-        if qty == 0 {
-            // Remove item if quantity is 0
-            cart.items.retain(|i| i.sku != sku);
-        } else {
-            item.qty = qty;
-        }
-
-        self.recalculate_totals(cart_id)?;
-
-        self.get_cart(cart_id)
+        to_js(cart)
     }
 
     /// Remove an item from the cart
     pub fn remove_item(&mut self, cart_id: &str, sku: &str) -> Result<JsValue, JsValue> {
-        let cart = self
-            .carts
-            .get_mut(cart_id)
-            .ok_or_else(|| JsValue::from_str(&format!("Cart {} not found", cart_id)))?;
+        let cart = self.cart_mut(cart_id)?;
+        cart.remove_item(sku);
 
-        cart.items.retain(|i| i.sku != sku);
-
-        self.recalculate_totals(cart_id)?;
-
-        self.get_cart(cart_id)
+        to_js(cart)
     }
 
     /// Add a coupon code
     pub fn add_coupon(&mut self, cart_id: &str, coupon: String) -> Result<JsValue, JsValue> {
-        let cart = self
-            .carts
-            .get_mut(cart_id)
-            .ok_or_else(|| JsValue::from_str(&format!("Cart {} not found", cart_id)))?;
+        let cart = self.cart_mut(cart_id)?;
+        cart.add_coupon(coupon);
 
-        if !cart.coupons.contains(&coupon) {
-            cart.coupons.push(coupon);
-        }
-
-        // Note: Actual coupon calculation would be done server-side
-        // This is just for tracking
-
-        self.get_cart(cart_id)
+        to_js(cart)
     }
 
     /// Calculate cart totals
     pub fn recalculate_totals(&mut self, cart_id: &str) -> Result<(), JsValue> {
-        let cart = self
-            .carts
-            .get_mut(cart_id)
-            .ok_or_else(|| JsValue::from_str(&format!("Cart {} not found", cart_id)))?;
-
-        let items_total: f64 = cart.items.iter().map(|item| item.price * item.qty as f64).sum();
-
-        cart.sum.items_total = items_total;
-        cart.sum.balance_due =
-            items_total + cart.sum.shipping_total + cart.sum.tax_total - cart.sum.discount_total;
+        self.cart_mut(cart_id)?.recalculate();
 
         Ok(())
     }
 
     /// Get cart item count
     pub fn get_item_count(&self, cart_id: &str) -> Result<u32, JsValue> {
-        let cart = self
-            .carts
-            .get(cart_id)
-            .ok_or_else(|| JsValue::from_str(&format!("Cart {} not found", cart_id)))?;
-
-        Ok(cart.items.iter().map(|item| item.qty).sum())
+        Ok(self.cart(cart_id)?.item_count())
     }
 
     /// Clear cart
     pub fn clear_cart(&mut self, cart_id: &str) -> Result<JsValue, JsValue> {
-        let cart = self
-            .carts
-            .get_mut(cart_id)
-            .ok_or_else(|| JsValue::from_str(&format!("Cart {} not found", cart_id)))?;
-
-        cart.items.clear();
-        cart.coupons.clear();
-        cart.sum = CartSummary {
-            items_total: 0.0,
-            shipping_total: 0.0,
-            tax_total: 0.0,
-            discount_total: 0.0,
-            balance_due: 0.0,
-        };
+        let cart = self.cart_mut(cart_id)?;
+        cart.clear();
 
-        self.get_cart(cart_id)
+        to_js(cart)
     }
 }
 
+impl CartManager {
+    /// Start an empty cart, replacing any cart with the same ID
+    pub fn create(&mut self, cart_id: String) -> &Cart {
+        self.carts
+            .insert(cart_id.clone(), Cart::new(cart_id.clone()));
+
+        &self.carts[&cart_id]
+    }
+
+    /// Keep a cart, e.g. one returned by the API. Returns its ID.
+    pub fn insert(&mut self, cart: Cart) -> String {
+        let cart_id = cart.cart_id.clone();
+        self.carts.insert(cart_id.clone(), cart);
+
+        cart_id
+    }
+
+    pub fn cart(&self, cart_id: &str) -> Result<&Cart, CartError> {
+        self.carts
+            .get(cart_id)
+            .ok_or_else(|| CartError::NotFound(cart_id.to_string()))
+    }
+
+    pub fn cart_mut(&mut self, cart_id: &str) -> Result<&mut Cart, CartError> {
+        self.carts
+            .get_mut(cart_id)
+            .ok_or_else(|| CartError::NotFound(cart_id.to_string()))
+    }
+}
+
+fn to_js(cart: &Cart) -> Result<JsValue, JsValue> {
+    serde_wasm_bindgen::to_value(cart)
+        .map_err(|e| JsValue::from_str(&format!("Failed to serialize cart: {}", e)))
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -238,7 +290,7 @@ mod tests {
 
         // Create cart
         let cart_id = "TEST_CART".to_string();
-        manager.create_cart(cart_id.clone()).unwrap();
+        manager.create(cart_id.clone());
 
         // Add item
         let item = CartItem {
@@ -251,10 +303,33 @@ mod tests {
             variations: None,
         };
 
-        let js_item = serde_wasm_bindgen::to_value(&item).unwrap();
-        manager.add_item(&cart_id, js_item).unwrap();
+        manager.cart_mut(&cart_id).unwrap().add_item(item);
 
         // Check item count
-        assert_eq!(manager.get_item_count(&cart_id).unwrap(), 1);
+        let cart = manager.cart(&cart_id).unwrap();
+        assert_eq!(cart.item_count(), 1);
+        assert_eq!(cart.sum.balance_due, 99.99);
+        assert_eq!(
+            manager.cart_mut(&cart_id).unwrap().update_item("NOPE", 2),
+            Err(CartError::ItemNotFound("NOPE".to_string()))
+        );
+        assert!(manager.cart("OTHER").is_err());
+    }
+
+    #[test]
+    fn test_cart_from_api_strings() {
+        let mut cart: Cart = serde_json::from_value(serde_json::json!({
+            "@ITEMS": [
+                { "sku": "BLUE", "product": "BLUE", "qty": "2", "price": "12.99", "base_price": "12.99" }
+            ],
+            "sum": { "shp_total": "5", "tax_total": "0.00", "balance_due_total": "12.99" }
+        }))
+        .unwrap();
+
+        cart.recalculate();
+        assert_eq!(cart.items[0].pid, "BLUE");
+        assert_eq!(cart.item_count(), 2);
+        assert_eq!(cart.sum.items_total, 25.98);
+        assert_eq!(cart.sum.balance_due, 30.98);
     }
 }
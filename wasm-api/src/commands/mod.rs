@@ -0,0 +1,510 @@
+use serde::de::DeserializeOwned;
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use std::collections::HashMap;
+use thiserror::Error;
+
+use crate::dispatch::{ApiMessage, ApiRequest, Priority, QueueType, RequestTag};
+use crate::product::Product;
+use crate::utils::{flag, number};
+
+/// Why a command could not be built or its response could not be read
+#[derive(Debug, Clone, PartialEq, Eq, Error, Serialize, Deserialize)]
+#[serde(tag = "kind", rename_all = "lowercase")]
+pub enum CommandError {
+    #[error("{cmd} requires {param}")]
+    Missing { cmd: String, param: String },
+    #[error("{cmd} has an invalid {param}: {reason}")]
+    Invalid {
+        cmd: String,
+        param: String,
+        reason: String,
+    },
+    #[error("unknown command {cmd}")]
+    Unknown { cmd: String },
+    #[error("unexpected {cmd} response: {message}")]
+    Response { cmd: String, message: String },
+}
+
+impl From<CommandError> for wasm_bindgen::JsValue {
+    fn from(error: CommandError) -> Self {
+        wasm_bindgen::JsValue::from_str(&error.to_string())
+    }
+}
+
+/// A JSON API command with typed params and a typed response
+pub trait Command: Serialize + DeserializeOwned {
+    /// The `_cmd` sent to the API
+    const CMD: &'static str;
+    /// The queue the command is dispatched on unless the caller picks another
+    const QUEUE: QueueType;
+    type Response: DeserializeOwned;
+
+    /// Check required params before anything is sent
+    fn validate(&self) -> Result<(), CommandError>;
+
+    /// Where the response is stored, if it is worth keeping
+    fn datapointer(&self) -> Option<String> {
+        None
+    }
+
+    /// Validate the command and build the request to queue
+    fn to_request(
+        &self,
+        callback: Option<String>,
+        extension: Option<String>,
+    ) -> Result<ApiRequest, CommandError> {
+        self.validate()?;
+
+        let params = match serde_json::to_value(self) {
+            Ok(Value::Object(map)) => map.into_iter().collect(),
+            _ => HashMap::new(),
+        };
+        let tag = match (self.datapointer(), callback) {
+            (None, None) => None,
+            (datapointer, callback) => Some(RequestTag {
+                datapointer: datapointer.unwrap_or_default(),
+                callback,
+                extension,
+            }),
+        };
+
+        Ok(ApiRequest {
+            cmd: Self::CMD.to_string(),
+            params,
+            tag,
+            uuid: None,
+            priority: Priority::default(),
+        })
+    }
+
+    /// Read the response to this command
+    fn parse_response(response: &Value) -> Result<Self::Response, CommandError> {
+        serde_json::from_value(response.clone()).map_err(|e| CommandError::Response {
+            cmd: Self::CMD.to_string(),
+            message: e.to_string(),
+        })
+    }
+}
+
+/// Build a request for a catalogued command from untyped params. Returns the
+/// command's default queue along with the request. The params are checked
+/// against the command but sent as given, so values the API accepts in more
+/// than one form (`"qty": "1"`) and params the command does not declare are kept.
+pub fn build_request(
+    cmd: &str,
+    params: Value,
+    callback: Option<String>,
+    extension: Option<String>,
+) -> Result<(QueueType, ApiRequest), CommandError> {
+    fn build<C: Command>(
+        params: Value,
+        callback: Option<String>,
+        extension: Option<String>,
+    ) -> Result<(QueueType, ApiRequest), CommandError> {
+        let command: C =
+            serde_json::from_value(params.clone()).map_err(|e| CommandError::Invalid {
+                cmd: C::CMD.to_string(),
+                param: "params".to_string(),
+                reason: e.to_string(),
+            })?;
+        let mut request = command.to_request(callback, extension)?;
+        if let Value::Object(map) = params {
+            request.params = map.into_iter().collect();
+        }
+
+        Ok((C::QUEUE, request))
+    }
+
+    match cmd {
+        AppProductGet::CMD => build::<AppProductGet>(params, callback, extension),
+        AppCartCreate::CMD => build::<AppCartCreate>(params, callback, extension),
+        CartItemAppend::CMD => build::<CartItemAppend>(params, callback, extension),
+        CartItemUpdate::CMD => build::<CartItemUpdate>(params, callback, extension),
+        CartCouponAdd::CMD => build::<CartCouponAdd>(params, callback, extension),
+        CartOrderCreate::CMD => build::<CartOrderCreate>(params, callback, extension),
+        AppBuyerLogin::CMD => build::<AppBuyerLogin>(params, callback, extension),
+        BuyerAddressList::CMD => build::<BuyerAddressList>(params, callback, extension),
+        AppPaymentMethods::CMD => build::<AppPaymentMethods>(params, callback, extension),
+        _ => Err(CommandError::Unknown {
+            cmd: cmd.to_string(),
+        }),
+    }
+}
+
+/// Check the params of a request for a catalogued command. Requests for
+/// commands outside the catalogue are passed through unchecked.
+pub fn validate_request(request: &ApiRequest) -> Result<(), CommandError> {
+    let params = Value::Object(request.params.clone().into_iter().collect());
+
+    match build_request(&request.cmd, params, None, None) {
+        Err(CommandError::Unknown { .. }) => Ok(()),
+        result => result.map(|_| ()),
+    }
+}
+
+fn require(cmd: &str, param: &str, value: &str) -> Result<(), CommandError> {
+    if value.trim().is_empty() {
+        return Err(CommandError::Missing {
+            cmd: cmd.to_string(),
+            param: param.to_string(),
+        });
+    }
+    Ok(())
+}
+
+/// Response to commands that only report success or failure
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct AckResponse {
+    #[serde(rename = "@MESSAGES", default)]
+    pub messages: Vec<ApiMessage>,
+}
+
+/// Get a product record (legacy `calls.appProductGet`)
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct AppProductGet {
+    pub pid: String,
+    #[serde(rename = "withVariations", default, with = "flag")]
+    pub with_variations: bool,
+    #[serde(rename = "withInventory", default, with = "flag")]
+    pub with_inventory: bool,
+}
+
+impl AppProductGet {
+    /// Product IDs are always upper case
+    pub fn new(pid: &str) -> AppProductGet {
+        AppProductGet {
+            pid: pid.to_uppercase(),
+            with_variations: true,
+            with_inventory: true,
+        }
+    }
+}
+
+impl Command for AppProductGet {
+    const CMD: &'static str = "appProductGet";
+    const QUEUE: QueueType = QueueType::Mutable;
+    type Response = Product;
+
+    fn validate(&self) -> Result<(), CommandError> {
+        require(Self::CMD, "pid", &self.pid)
+    }
+
+    fn datapointer(&self) -> Option<String> {
+        Some(format!("appProductGet|{}", self.pid))
+    }
+}
+
+/// Start a new cart (legacy `calls.appCartCreate`)
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct AppCartCreate {}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct CartCreateResponse {
+    #[serde(rename = "_cartid")]
+    pub cart_id: String,
+}
+
+impl Command for AppCartCreate {
+    const CMD: &'static str = "appCartCreate";
+    const QUEUE: QueueType = QueueType::Immutable;
+    type Response = CartCreateResponse;
+
+    fn validate(&self) -> Result<(), CommandError> {
+        Ok(())
+    }
+}
+
+/// Add an item to a cart (legacy `cco.calls.cartItemAppend`)
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct CartItemAppend {
+    #[serde(rename = "_cartid")]
+    pub cart_id: String,
+    /// A product ID or a fully qualified SKU with options
+    pub sku: String,
+    #[serde(deserialize_with = "number::deserialize")]
+    pub qty: u32,
+    /// Selected options, variation id -> option value, inventoriable or not
+    #[serde(
+        rename = "%variations",
+        default,
+        skip_serializing_if = "HashMap::is_empty"
+    )]
+    pub variations: HashMap<String, String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub uuid: Option<String>,
+}
+
+impl Command for CartItemAppend {
+    const CMD: &'static str = "cartItemAppend";
+    const QUEUE: QueueType = QueueType::Immutable;
+    type Response = AckResponse;
+
+    fn validate(&self) -> Result<(), CommandError> {
+        require(Self::CMD, "_cartid", &self.cart_id)?;
+        require(Self::CMD, "sku", &self.sku)?;
+        if self.qty == 0 {
+            return Err(CommandError::Invalid {
+                cmd: Self::CMD.to_string(),
+                param: "qty".to_string(),
+                reason: "must be at least 1".to_string(),
+            });
+        }
+        Ok(())
+    }
+}
+
+/// Change the quantity of a cart item. A quantity of 0 removes it. Buyer
+/// sessions name the item by `stid`; admin sessions by `uuid`, with `qty`.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct CartItemUpdate {
+    #[serde(rename = "_cartid")]
+    pub cart_id: String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub stid: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub uuid: Option<String>,
+    #[serde(alias = "qty", deserialize_with = "number::deserialize")]
+    pub quantity: u32,
+}
+
+impl Command for CartItemUpdate {
+    const CMD: &'static str = "cartItemUpdate";
+    const QUEUE: QueueType = QueueType::Immutable;
+    type Response = AckResponse;
+
+    fn validate(&self) -> Result<(), CommandError> {
+        require(Self::CMD, "_cartid", &self.cart_id)?;
+        match (&self.stid, &self.uuid) {
+            (Some(stid), _) if !stid.trim().is_empty() => Ok(()),
+            (_, Some(uuid)) => require(Self::CMD, "uuid", uuid),
+            _ => require(Self::CMD, "stid", ""),
+        }
+    }
+}
+
+/// Apply a coupon code to a cart
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct CartCouponAdd {
+    #[serde(rename = "_cartid")]
+    pub cart_id: String,
+    pub coupon: String,
+}
+
+impl Command for CartCouponAdd {
+    const CMD: &'static str = "cartCouponAdd";
+    const QUEUE: QueueType = QueueType::Immutable;
+    type Response = AckResponse;
+
+    fn validate(&self) -> Result<(), CommandError> {
+        require(Self::CMD, "_cartid", &self.cart_id)?;
+        require(Self::CMD, "coupon", &self.coupon)
+    }
+}
+
+/// Turn a cart into an order
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct CartOrderCreate {
+    #[serde(rename = "_cartid")]
+    pub cart_id: String,
+    #[serde(rename = "@PAYMENTS", default)]
+    pub payments: Vec<Value>,
+    /// Return immediately and poll `cartOrderStatus` for the outcome
+    #[serde(rename = "async", default, with = "flag")]
+    pub is_async: bool,
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct OrderCreateResponse {
+    #[serde(default)]
+    pub orderid: Option<String>,
+    #[serde(default, with = "flag")]
+    pub finished: bool,
+    /// Cart ID to poll `cartOrderStatus` with while an async order is processed
+    #[serde(rename = "status-cartid", default)]
+    pub status_cart_id: Option<String>,
+}
+
+impl Command for CartOrderCreate {
+    const CMD: &'static str = "cartOrderCreate";
+    const QUEUE: QueueType = QueueType::Immutable;
+    type Response = OrderCreateResponse;
+
+    fn validate(&self) -> Result<(), CommandError> {
+        require(Self::CMD, "_cartid", &self.cart_id)
+    }
+
+    fn datapointer(&self) -> Option<String> {
+        Some(format!("cartOrderCreate|{}", self.cart_id))
+    }
+}
+
+/// Log a buyer in
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct AppBuyerLogin {
+    pub login: String,
+    pub password: String,
+    #[serde(default = "AppBuyerLogin::default_method")]
+    pub method: String,
+}
+
+impl AppBuyerLogin {
+    pub fn new(login: &str, password: &str) -> AppBuyerLogin {
+        AppBuyerLogin {
+            login: login.to_string(),
+            password: password.to_string(),
+            method: AppBuyerLogin::default_method(),
+        }
+    }
+
+    fn default_method() -> String {
+        "unsecure".to_string()
+    }
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct BuyerLoginResponse {
+    /// Customer ID, set when the login succeeded
+    #[serde(default)]
+    pub cid: Option<Value>,
+}
+
+impl Command for AppBuyerLogin {
+    const CMD: &'static str = "appBuyerLogin";
+    const QUEUE: QueueType = QueueType::Immutable;
+    type Response = BuyerLoginResponse;
+
+    fn validate(&self) -> Result<(), CommandError> {
+        require(Self::CMD, "login", &self.login)?;
+        require(Self::CMD, "password", &self.password)
+    }
+
+    fn datapointer(&self) -> Option<String> {
+        Some("appBuyerLogin".to_string())
+    }
+}
+
+/// List the logged in buyer's addresses
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct BuyerAddressList {}
+
+/// A saved address. Fields are keyed like `bill/address1` or `ship/city`.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Address {
+    #[serde(rename = "_id")]
+    pub id: String,
+    #[serde(flatten)]
+    pub fields: HashMap<String, Value>,
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct AddressListResponse {
+    #[serde(rename = "@bill", default)]
+    pub bill: Vec<Address>,
+    #[serde(rename = "@ship", default)]
+    pub ship: Vec<Address>,
+}
+
+impl Command for BuyerAddressList {
+    const CMD: &'static str = "buyerAddressList";
+    const QUEUE: QueueType = QueueType::Mutable;
+    type Response = AddressListResponse;
+
+    fn validate(&self) -> Result<(), CommandError> {
+        Ok(())
+    }
+
+    fn datapointer(&self) -> Option<String> {
+        Some("buyerAddressList".to_string())
+    }
+}
+
+/// Payment methods available for a cart
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct AppPaymentMethods {
+    #[serde(rename = "_cartid")]
+    pub cart_id: String,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct PaymentMethod {
+    pub id: String,
+    #[serde(default)]
+    pub pretty: String,
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct PaymentMethodsResponse {
+    #[serde(rename = "@methods", default)]
+    pub methods: Vec<PaymentMethod>,
+}
+
+impl Command for AppPaymentMethods {
+    const CMD: &'static str = "appPaymentMethods";
+    const QUEUE: QueueType = QueueType::Immutable;
+    type Response = PaymentMethodsResponse;
+
+    fn validate(&self) -> Result<(), CommandError> {
+        require(Self::CMD, "_cartid", &self.cart_id)
+    }
+
+    fn datapointer(&self) -> Option<String> {
+        Some(format!("appPaymentMethods|{}", self.cart_id))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    #[test]
+    fn test_command_validation() {
+        let append = CartItemAppend {
+            cart_id: "CART1".to_string(),
+            sku: "TEST:0201".to_string(),
+            qty: 0,
+            variations: HashMap::new(),
+            uuid: None,
+        };
+        assert!(matches!(
+            append.to_request(None, None),
+            Err(CommandError::Invalid { .. })
+        ));
+
+        let login = AppBuyerLogin::new("buyer@example.com", "");
+        assert_eq!(
+            login.validate().unwrap_err().to_string(),
+            "appBuyerLogin requires password"
+        );
+
+        // Untyped requests for catalogued commands are checked too
+        let request = ApiRequest {
+            cmd: "cartCouponAdd".to_string(),
+            params: HashMap::from([("_cartid".to_string(), json!("CART1"))]),
+            tag: None,
+            uuid: None,
+            priority: Priority::default(),
+        };
+        assert!(validate_request(&request).is_err());
+    }
+
+    #[test]
+    fn test_command_request_and_response() {
+        let request = AppProductGet::new("test")
+            .to_request(Some("showProduct".to_string()), None)
+            .unwrap();
+        assert_eq!(request.cmd, "appProductGet");
+        assert_eq!(request.params["pid"], "TEST");
+        assert_eq!(request.params["withInventory"], 1);
+        assert_eq!(request.tag.unwrap().datapointer, "appProductGet|TEST");
+
+        let response =
+            json!({ "_rcmd": "cartOrderCreate", "orderid": "2014-02-809", "finished": "1" });
+        let order = CartOrderCreate::parse_response(&response).unwrap();
+        assert_eq!(order.orderid.as_deref(), Some("2014-02-809"));
+        assert!(order.finished);
+
+        assert!(AppCartCreate::parse_response(&json!({})).is_err());
+    }
+}
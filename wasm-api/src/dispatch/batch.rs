@@ -0,0 +1,69 @@
+use serde::{Deserialize, Serialize};
+
+/// Largest pipeline sent at once. A drain that goes over either limit is split,
+/// and the requests that do not fit stay queued for the next batch.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub struct BatchLimits {
+    pub max_requests: usize,
+    /// Serialized size of the requests in the batch, in bytes
+    pub max_bytes: usize,
+}
+
+impl Default for BatchLimits {
+    fn default() -> BatchLimits {
+        BatchLimits {
+            max_requests: 50,
+            max_bytes: 64 * 1024,
+        }
+    }
+}
+
+impl BatchLimits {
+    pub fn unlimited() -> BatchLimits {
+        BatchLimits {
+            max_requests: usize::MAX,
+            max_bytes: usize::MAX,
+        }
+    }
+
+    /// How many of the leading requests, given their serialized sizes, fit in one
+    /// batch. A request larger than `max_bytes` is still sent, on its own.
+    pub fn chunk_len(&self, sizes: impl IntoIterator<Item = usize>) -> usize {
+        let mut count = 0;
+        let mut bytes = 0usize;
+
+        for size in sizes {
+            if count >= self.max_requests.max(1) {
+                break;
+            }
+            // Requests are joined with a comma in the `@cmds` array
+            let added = size + usize::from(count > 0);
+            if count > 0 && bytes.saturating_add(added) > self.max_bytes {
+                break;
+            }
+            bytes = bytes.saturating_add(added);
+            count += 1;
+        }
+
+        count
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_chunk_len() {
+        let limits = BatchLimits {
+            max_requests: 3,
+            max_bytes: 100,
+        };
+        assert_eq!(limits.chunk_len([10, 10, 10, 10]), 3);
+        assert_eq!(limits.chunk_len([60, 40, 10]), 1);
+        assert_eq!(limits.chunk_len([60, 39, 10]), 2);
+        assert_eq!(limits.chunk_len([500, 10]), 1);
+        assert_eq!(limits.chunk_len([]), 0);
+        assert_eq!(BatchLimits::unlimited().chunk_len(vec![1000; 500]), 500);
+    }
+}
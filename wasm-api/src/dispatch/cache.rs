@@ -0,0 +1,224 @@
+use wasm_bindgen::prelude::*;
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use std::collections::HashMap;
+
+use super::storage::{MemoryStorage, StorageBackend};
+
+/// Where a response is kept once it has been received
+#[wasm_bindgen]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize)]
+pub enum StorageTier {
+    Memory,  // Gone on reload
+    Session, // sessionStorage, gone when the browser closes
+    Local,   // localStorage, carried between sessions
+}
+
+/// Per-command TTLs and storage tiers (legacy `thisGetsSaved2*` and `fetchData` expiry)
+#[derive(Debug, Clone)]
+pub struct CachePolicy {
+    /// TTL in seconds for commands without an explicit TTL
+    pub default_ttl: u64,
+    pub ttls: HashMap<String, u64>,
+    /// Tiers per command. Commands not listed are kept in memory only.
+    pub tiers: HashMap<String, Vec<StorageTier>>,
+}
+
+impl Default for CachePolicy {
+    fn default() -> CachePolicy {
+        let mut ttls = HashMap::new();
+        ttls.insert("authAdminLogin".to_string(), 60 * 60 * 24 * 15);
+
+        let mut tiers = HashMap::new();
+        // Saved into the category object / reflected in the cart object already
+        tiers.insert("appPageGet".to_string(), vec![]);
+        tiers.insert("cartSet".to_string(), vec![]);
+        // Must be carried between sessions
+        tiers.insert(
+            "authAdminLogin".to_string(),
+            vec![StorageTier::Memory, StorageTier::Local],
+        );
+
+        CachePolicy {
+            default_ttl: 60 * 60 * 24,
+            ttls,
+            tiers,
+        }
+    }
+}
+
+impl CachePolicy {
+    pub fn ttl(&self, cmd: &str) -> u64 {
+        self.ttls.get(cmd).copied().unwrap_or(self.default_ttl)
+    }
+
+    pub fn tiers(&self, cmd: &str) -> &[StorageTier] {
+        self.tiers
+            .get(cmd)
+            .map(|tiers| tiers.as_slice())
+            .unwrap_or(&[StorageTier::Memory])
+    }
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+struct CacheEntry {
+    ts: u64,
+    expires: u64,
+    data: Value,
+}
+
+/// Response data keyed by datapointer (legacy `_app.data` plus local/session storage)
+pub struct DatapointerCache {
+    policy: CachePolicy,
+    memory: HashMap<String, CacheEntry>,
+    session: Box<dyn StorageBackend>,
+    local: Box<dyn StorageBackend>,
+}
+
+impl Default for DatapointerCache {
+    fn default() -> DatapointerCache {
+        DatapointerCache::new(
+            CachePolicy::default(),
+            Box::new(MemoryStorage::new()),
+            Box::new(MemoryStorage::new()),
+        )
+    }
+}
+
+impl DatapointerCache {
+    pub fn new(
+        policy: CachePolicy,
+        session: Box<dyn StorageBackend>,
+        local: Box<dyn StorageBackend>,
+    ) -> DatapointerCache {
+        DatapointerCache {
+            policy,
+            memory: HashMap::new(),
+            session,
+            local,
+        }
+    }
+
+    pub fn policy(&self) -> &CachePolicy {
+        &self.policy
+    }
+
+    pub fn policy_mut(&mut self) -> &mut CachePolicy {
+        &mut self.policy
+    }
+
+    /// Replace the session and local storage backends
+    pub fn set_backends(
+        &mut self,
+        session: Box<dyn StorageBackend>,
+        local: Box<dyn StorageBackend>,
+    ) {
+        self.session = session;
+        self.local = local;
+    }
+
+    /// Store a response for `cmd` in every tier the policy assigns to it
+    pub fn insert(&mut self, cmd: &str, datapointer: &str, data: Value, now: u64) {
+        let entry = CacheEntry {
+            ts: now,
+            expires: now + self.policy.ttl(cmd),
+            data,
+        };
+        let tiers = self.policy.tiers(cmd).to_vec();
+
+        if tiers.contains(&StorageTier::Session) || tiers.contains(&StorageTier::Local) {
+            if let Ok(json) = serde_json::to_string(&entry) {
+                if tiers.contains(&StorageTier::Session) {
+                    self.session.set(datapointer, json.clone());
+                }
+                if tiers.contains(&StorageTier::Local) {
+                    self.local.set(datapointer, json);
+                }
+            }
+        }
+        if tiers.contains(&StorageTier::Memory) {
+            self.memory.insert(datapointer.to_string(), entry);
+        }
+    }
+
+    /// Get fresh data for a datapointer (legacy `fetchData`). Memory is checked
+    /// first, then session and local storage; a fresh stored hit is promoted into memory.
+    pub fn get(&mut self, datapointer: &str, now: u64) -> Option<&Value> {
+        if self
+            .memory
+            .get(datapointer)
+            .is_none_or(|e| now >= e.expires)
+        {
+            let entry = self.read_stored(datapointer).filter(|e| now < e.expires)?;
+            self.memory.insert(datapointer.to_string(), entry);
+        }
+
+        self.memory.get(datapointer).map(|e| &e.data)
+    }
+
+    /// Whether a datapointer has data that has not expired, in any tier
+    pub fn is_fresh(&self, datapointer: &str, now: u64) -> bool {
+        match self.memory.get(datapointer) {
+            Some(entry) if now < entry.expires => true,
+            _ => self
+                .read_stored(datapointer)
+                .is_some_and(|e| now < e.expires),
+        }
+    }
+
+    /// Remove a datapointer from every tier (legacy `destroy`)
+    pub fn remove(&mut self, datapointer: &str) {
+        self.memory.remove(datapointer);
+        self.session.remove(datapointer);
+        self.local.remove(datapointer);
+    }
+
+    fn read_stored(&self, datapointer: &str) -> Option<CacheEntry> {
+        self.session
+            .get(datapointer)
+            .or_else(|| self.local.get(datapointer))
+            .and_then(|json| serde_json::from_str(&json).ok())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    #[test]
+    fn test_cache_ttl_and_tiers() {
+        let mut cache = DatapointerCache::default();
+        cache
+            .policy_mut()
+            .ttls
+            .insert("appProductGet".to_string(), 60);
+
+        cache.insert(
+            "appProductGet",
+            "appProductGet|TEST",
+            json!({"pid": "TEST"}),
+            1000,
+        );
+        assert!(cache.is_fresh("appProductGet|TEST", 1059));
+        assert!(!cache.is_fresh("appProductGet|TEST", 1060));
+        assert!(cache.get("appProductGet|TEST", 1060).is_none());
+
+        // A reload empties memory but not localStorage
+        cache.insert(
+            "authAdminLogin",
+            "authAdminLogin",
+            json!({"authtoken": "abc"}),
+            1000,
+        );
+        cache.memory.clear();
+        assert_eq!(
+            cache.get("authAdminLogin", 2000).unwrap()["authtoken"],
+            "abc"
+        );
+        assert!(cache.memory.contains_key("authAdminLogin"));
+
+        cache.remove("authAdminLogin");
+        assert!(!cache.is_fresh("authAdminLogin", 2000));
+    }
+}
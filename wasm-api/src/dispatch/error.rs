@@ -0,0 +1,232 @@
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use thiserror::Error;
+
+/// Message types the API uses for errors (legacy `responseHasErrors`)
+const ERROR_TYPES: [&str; 6] = ["youerr", "fileerr", "apperr", "apierr", "iseerr", "cfgerr"];
+
+/// Message types that are warnings, not errors
+const WARNING_TYPES: [&str; 3] = ["warn", "warning", "youwarn"];
+
+/// An entry in a response's `@MESSAGES`
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ApiMessage {
+    #[serde(rename = "@CODE", default)]
+    pub code: String,
+    #[serde(rename = "@TYPE", default)]
+    pub msg_type: String,
+    #[serde(rename = "@TEXT", default)]
+    pub text: String,
+}
+
+impl ApiMessage {
+    pub fn is_error(&self) -> bool {
+        ERROR_TYPES.contains(&self.msg_type.as_str()) || self.msg_type.eq_ignore_ascii_case("error")
+    }
+
+    pub fn is_warning(&self) -> bool {
+        is_warning_type(&self.msg_type)
+    }
+}
+
+/// Why a request did not produce usable data
+#[derive(Debug, Clone, PartialEq, Eq, Error, Serialize, Deserialize)]
+#[serde(tag = "kind", rename_all = "lowercase")]
+pub enum ApiError {
+    /// A command-level error reported by the API (youerr, apperr, apierr, ...)
+    #[error("{errtype} {code}: {message}")]
+    Api {
+        code: String,
+        errtype: String,
+        message: String,
+    },
+    /// Internal server error. No individual responses are returned for the batch.
+    #[error("internal server error {code}: {message}")]
+    Ise { code: String, message: String },
+    /// The requested object does not exist
+    #[error("missing {code}: {message}")]
+    Missing { code: String, message: String },
+    /// The request never produced a response
+    #[error("request failed: {message}")]
+    Transport { message: String },
+}
+
+impl ApiError {
+    fn from_parts(code: String, errtype: &str, message: String) -> ApiError {
+        match errtype {
+            "iseerr" | "ise" => ApiError::Ise { code, message },
+            "missing" => ApiError::Missing { code, message },
+            _ => ApiError::Api {
+                code,
+                errtype: errtype.to_string(),
+                message,
+            },
+        }
+    }
+}
+
+impl From<ApiError> for wasm_bindgen::JsValue {
+    fn from(error: ApiError) -> Self {
+        wasm_bindgen::JsValue::from_str(&error.to_string())
+    }
+}
+
+/// All messages attached to a response: `@MESSAGES` plus the older `_msg_N_*` fields
+pub fn messages(response: &Value) -> Vec<ApiMessage> {
+    let mut messages: Vec<ApiMessage> = response
+        .get("@MESSAGES")
+        .and_then(|m| serde_json::from_value(m.clone()).ok())
+        .unwrap_or_default();
+
+    // The _msg format index starts at one, not zero
+    for i in 1..=field_u64(response, "_msgs") {
+        messages.push(ApiMessage {
+            code: field_string(response, &format!("_msg_{}_id", i)),
+            msg_type: field_string(response, &format!("_msg_{}_type", i)),
+            text: field_string(response, &format!("_msg_{}_txt", i)),
+        });
+    }
+
+    messages
+}
+
+/// Whether a response is an error on the pipe itself rather than a command
+/// response: `_rcmd` "err", or a response without `@rcmds` that is an error.
+/// A solo response carrying only a warning is not.
+pub fn is_pipe_error(response: &Value) -> bool {
+    response.get("_rcmd").and_then(Value::as_str) == Some("err")
+        || (response.get("@rcmds").is_none()
+            && field_u64(response, "errid") > 0
+            && check_response("pipeline", response).is_err())
+}
+
+/// Whether a response says the admin auth token has expired (errid 10)
+pub fn is_token_expired(response: &Value) -> bool {
+    field_u64(response, "errid") == 10
+}
+
+/// Classify the response to `cmd` (legacy `responseIsMissing`/`responseHasErrors`).
+/// Returns the non-error messages (warnings, info) for a successful response.
+pub fn check_response(cmd: &str, response: &Value) -> Result<Vec<ApiMessage>, ApiError> {
+    let errtype = field_string(response, "errtype");
+    if errtype == "missing" {
+        return Err(ApiError::Missing {
+            code: field_string(response, "errid"),
+            message: field_string(response, "errmsg"),
+        });
+    }
+
+    // The API doesn't treat some empty results as errors; the app does
+    match cmd {
+        "appProductGet" | "adminProductDetail" if response.pointer("/%attribs/db:id").is_none() => {
+            return Err(ApiError::Missing {
+                code: "MVC-M-100".to_string(),
+                message: format!(
+                    "could not find product {}. Product may no longer exist.",
+                    field_string(response, "pid")
+                ),
+            });
+        }
+        "appCartCreate" if response.get("_cartid").is_none() => {
+            return Err(ApiError::Api {
+                code: "MVC-M-150".to_string(),
+                errtype: "apperr".to_string(),
+                message: "appCartCreate response did not contain a _cartid.".to_string(),
+            });
+        }
+        "appNavcatDetail"
+            if field_u64(response, "errid") > 0 || field_string(response, "exists") == "0" =>
+        {
+            return Err(ApiError::Api {
+                code: "MVC-M-200".to_string(),
+                errtype: "apperr".to_string(),
+                message: "could not find category (may not exist)".to_string(),
+            });
+        }
+        _ => {}
+    }
+
+    // Warnings do not constitute errors
+    if field_u64(response, "errid") > 0 && !is_warning_type(&errtype) {
+        return Err(ApiError::from_parts(
+            field_string(response, "errid"),
+            &errtype,
+            field_string(response, "errmsg"),
+        ));
+    }
+
+    let messages = messages(response);
+    if let Some(error) = messages.iter().find(|m| m.is_error()) {
+        return Err(ApiError::from_parts(
+            error.code.clone(),
+            &error.msg_type,
+            error.text.clone(),
+        ));
+    }
+
+    Ok(messages)
+}
+
+fn is_warning_type(msg_type: &str) -> bool {
+    WARNING_TYPES.contains(&msg_type.to_ascii_lowercase().as_str())
+}
+
+/// Read a field as a string, whether the API sent it as a string or a number
+fn field_string(response: &Value, key: &str) -> String {
+    match response.get(key) {
+        Some(Value::String(s)) => s.clone(),
+        Some(Value::Number(n)) => n.to_string(),
+        _ => String::new(),
+    }
+}
+
+fn field_u64(response: &Value, key: &str) -> u64 {
+    match response.get(key) {
+        Some(Value::Number(n)) => n.as_u64().unwrap_or(0),
+        Some(Value::String(s)) => s.parse().unwrap_or(0),
+        _ => 0,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    #[test]
+    fn test_check_response_classification() {
+        let warning = json!({
+            "@MESSAGES": [{ "@CODE": "100", "@TYPE": "warn", "@TEXT": "Low stock" }]
+        });
+        let messages = check_response("cartDetail", &warning).unwrap();
+        assert!(messages[0].is_warning());
+
+        let solo_warning =
+            json!({ "_uuid": 1000, "errid": 7, "errtype": "youwarn", "errmsg": "Low stock" });
+        assert!(!is_pipe_error(&solo_warning));
+        assert!(check_response("cartItemAppend", &solo_warning).is_ok());
+        let youwarn = json!({ "_msgs": 1, "_msg_1_type": "youwarn", "_msg_1_id": "7", "_msg_1_txt": "Low stock" });
+        assert!(check_response("cartItemAppend", &youwarn).unwrap()[0].is_warning());
+
+        let ise = json!({ "errid": 500, "errtype": "iseerr", "errmsg": "boom" });
+        assert!(is_pipe_error(&ise));
+        assert!(matches!(
+            check_response("cartDetail", &ise),
+            Err(ApiError::Ise { .. })
+        ));
+
+        let legacy = json!({ "_msgs": 1, "_msg_1_type": "youerr", "_msg_1_id": "7", "_msg_1_txt": "Bad qty" });
+        assert_eq!(
+            check_response("cartItemAppend", &legacy)
+                .unwrap_err()
+                .to_string(),
+            "youerr 7: Bad qty"
+        );
+
+        let missing = json!({ "pid": "NOPE", "%attribs": {} });
+        assert!(matches!(
+            check_response("appProductGet", &missing),
+            Err(ApiError::Missing { .. })
+        ));
+    }
+}
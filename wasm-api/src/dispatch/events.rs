@@ -0,0 +1,139 @@
+use wasm_bindgen::prelude::*;
+use serde::{Deserialize, Serialize};
+
+use super::error::ApiError;
+use super::QueueType;
+
+/// Something that happened to a dispatch
+#[wasm_bindgen]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize)]
+pub enum DispatchEventKind {
+    Enqueued,  // Pushed onto a queue, or coalesced into a queued request
+    Sent,      // Taken for a batch
+    Completed, // Response handled
+    Failed,    // Failed for good
+    Aborted,   // Cancelled before a response was handled
+    Retried,   // Failed, and scheduled to be sent again
+}
+
+/// What listeners are told about each dispatch event
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct DispatchEvent {
+    pub kind: DispatchEventKind,
+    pub uuid: u32,
+    pub queue_type: QueueType,
+    pub cmd: String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub datapointer: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub error: Option<ApiError>,
+}
+
+struct Listener {
+    id: u32,
+    /// `None` listens to every kind
+    kind: Option<DispatchEventKind>,
+    callback: Box<dyn Fn(&DispatchEvent)>,
+}
+
+/// Listeners subscribed to a queue's events
+#[derive(Default)]
+pub struct EventBus {
+    listeners: Vec<Listener>,
+    next_id: u32,
+}
+
+impl EventBus {
+    /// Call `callback` for events of `kind`, or for every event if `kind` is `None`.
+    /// Returns an ID for `unsubscribe`.
+    pub fn subscribe(
+        &mut self,
+        kind: Option<DispatchEventKind>,
+        callback: impl Fn(&DispatchEvent) + 'static,
+    ) -> u32 {
+        self.next_id += 1;
+        self.listeners.push(Listener {
+            id: self.next_id,
+            kind,
+            callback: Box::new(callback),
+        });
+
+        self.next_id
+    }
+
+    pub fn unsubscribe(&mut self, id: u32) -> bool {
+        let before = self.listeners.len();
+        self.listeners.retain(|listener| listener.id != id);
+        before != self.listeners.len()
+    }
+
+    pub fn emit(&self, event: DispatchEvent) {
+        for listener in &self.listeners {
+            if listener.kind.is_none_or(|kind| kind == event.kind) {
+                (listener.callback)(&event);
+            }
+        }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.listeners.is_empty()
+    }
+}
+
+/// Wrap a JS function as a listener. The call is deferred to a microtask so the
+/// listener can read the queue: it is still borrowed while the event is emitted.
+pub fn js_listener(function: js_sys::Function) -> impl Fn(&DispatchEvent) + 'static {
+    move |event| {
+        let Ok(value) = event.serialize(&serde_wasm_bindgen::Serializer::json_compatible()) else {
+            return;
+        };
+        let function = function.clone();
+        wasm_bindgen_futures::spawn_local(async move {
+            let _ = function.call1(&JsValue::NULL, &value);
+        });
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::cell::RefCell;
+    use std::rc::Rc;
+
+    #[test]
+    fn test_subscribe_by_kind() {
+        let mut bus = EventBus::default();
+        let seen = Rc::new(RefCell::new(vec![]));
+        let event = |kind| DispatchEvent {
+            kind,
+            uuid: 1000,
+            queue_type: QueueType::Mutable,
+            cmd: "appProductGet".to_string(),
+            datapointer: None,
+            error: None,
+        };
+
+        let all = seen.clone();
+        let any = bus.subscribe(None, move |e| all.borrow_mut().push(("any", e.kind)));
+        let sent = seen.clone();
+        bus.subscribe(Some(DispatchEventKind::Sent), move |e| {
+            sent.borrow_mut().push(("sent", e.kind))
+        });
+
+        bus.emit(event(DispatchEventKind::Enqueued));
+        bus.emit(event(DispatchEventKind::Sent));
+        assert!(bus.unsubscribe(any));
+        assert!(!bus.unsubscribe(any));
+        bus.emit(event(DispatchEventKind::Sent));
+
+        assert_eq!(
+            *seen.borrow(),
+            vec![
+                ("any", DispatchEventKind::Enqueued),
+                ("any", DispatchEventKind::Sent),
+                ("sent", DispatchEventKind::Sent),
+                ("sent", DispatchEventKind::Sent),
+            ]
+        );
+    }
+}
@@ -0,0 +1,38 @@
+use serde::{Deserialize, Serialize};
+
+use super::error::ApiError;
+
+/// Requests pushed together that complete or cancel as one unit (legacy `pipeUUID`)
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct PipelineGroup {
+    pub members: Vec<u32>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub callback: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub extension: Option<String>,
+    /// The first error a member failed with
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub error: Option<ApiError>,
+}
+
+/// A group whose members have all finished. Reported once, with the group's callback.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct GroupResolution {
+    pub pipe_uuid: u32,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub callback: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub extension: Option<String>,
+    pub completed: Vec<u32>,
+    pub failed: Vec<u32>,
+    pub cancelled: Vec<u32>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub error: Option<ApiError>,
+}
+
+impl GroupResolution {
+    /// Whether every member completed
+    pub fn is_success(&self) -> bool {
+        self.failed.is_empty() && self.cancelled.is_empty()
+    }
+}
@@ -0,0 +1,367 @@
+use serde::{Deserialize, Serialize};
+use std::collections::{BTreeMap, HashMap};
+use std::fmt::Write;
+
+use super::events::DispatchEventKind;
+use super::QueueType;
+
+/// Bucket upper bounds for request timings, in ms. 50ms is the cart target.
+const LATENCY_BOUNDS_MS: [f64; 11] = [
+    5.0, 10.0, 25.0, 50.0, 100.0, 250.0, 500.0, 1000.0, 2500.0, 5000.0, 10000.0,
+];
+const BATCH_SIZE_BOUNDS: [f64; 7] = [1.0, 2.0, 5.0, 10.0, 25.0, 50.0, 100.0];
+const BATCH_BYTES_BOUNDS: [f64; 6] = [1024.0, 4096.0, 16384.0, 65536.0, 262144.0, 1048576.0];
+
+/// Observations counted into fixed buckets, Prometheus style
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Histogram {
+    pub bounds: Vec<f64>,
+    /// Observations per bucket, not cumulative. The last bucket is everything
+    /// over the highest bound.
+    pub counts: Vec<u64>,
+    pub sum: f64,
+    pub count: u64,
+}
+
+impl Histogram {
+    pub fn new(bounds: &[f64]) -> Histogram {
+        Histogram {
+            bounds: bounds.to_vec(),
+            counts: vec![0; bounds.len() + 1],
+            sum: 0.0,
+            count: 0,
+        }
+    }
+
+    pub fn observe(&mut self, value: f64) {
+        let bucket = self
+            .bounds
+            .iter()
+            .position(|bound| value <= *bound)
+            .unwrap_or(self.bounds.len());
+        self.counts[bucket] += 1;
+        self.sum += value;
+        self.count += 1;
+    }
+
+    pub fn mean(&self) -> Option<f64> {
+        (self.count > 0).then(|| self.sum / self.count as f64)
+    }
+
+    /// Upper bound of the bucket holding the `q` quantile, e.g. 0.95 for p95.
+    /// Infinite if it falls past the highest bound.
+    pub fn quantile(&self, q: f64) -> Option<f64> {
+        if self.count == 0 {
+            return None;
+        }
+        let rank = (q.clamp(0.0, 1.0) * self.count as f64).ceil().max(1.0) as u64;
+
+        let mut seen = 0;
+        for (bucket, count) in self.counts.iter().enumerate() {
+            seen += count;
+            if seen >= rank {
+                return Some(self.bounds.get(bucket).copied().unwrap_or(f64::INFINITY));
+            }
+        }
+        None
+    }
+}
+
+/// Counters and timings for one command
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct CommandMetrics {
+    pub sent: u64,
+    pub completed: u64,
+    pub failed: u64,
+    pub retried: u64,
+    pub aborted: u64,
+    /// From being sent the first time to being completed or failed, across retries
+    pub latency_ms: Histogram,
+    /// From being pushed to being sent the first time
+    pub queue_wait_ms: Histogram,
+}
+
+impl Default for CommandMetrics {
+    fn default() -> CommandMetrics {
+        CommandMetrics {
+            sent: 0,
+            completed: 0,
+            failed: 0,
+            retried: 0,
+            aborted: 0,
+            latency_ms: Histogram::new(&LATENCY_BOUNDS_MS),
+            queue_wait_ms: Histogram::new(&LATENCY_BOUNDS_MS),
+        }
+    }
+}
+
+impl CommandMetrics {
+    /// Share of finished requests that failed for good
+    pub fn error_rate(&self) -> f64 {
+        match self.completed + self.failed {
+            0 => 0.0,
+            finished => self.failed as f64 / finished as f64,
+        }
+    }
+}
+
+/// Sizes of the batches taken from one queue
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct BatchMetrics {
+    pub requests: Histogram,
+    pub bytes: Histogram,
+}
+
+impl Default for BatchMetrics {
+    fn default() -> BatchMetrics {
+        BatchMetrics {
+            requests: Histogram::new(&BATCH_SIZE_BOUNDS),
+            bytes: Histogram::new(&BATCH_BYTES_BOUNDS),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, Default)]
+struct Timing {
+    enqueued_ms: u64,
+    sent_ms: Option<u64>,
+    waited: bool,
+}
+
+/// Everything the queue has measured since it was created or last reset
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct DispatchMetrics {
+    pub commands: BTreeMap<String, CommandMetrics>,
+    pub batches: HashMap<QueueType, BatchMetrics>,
+    #[serde(skip)]
+    timings: HashMap<u32, Timing>,
+}
+
+impl DispatchMetrics {
+    /// Count a request event that happened at `now_ms`
+    pub fn record(&mut self, kind: DispatchEventKind, uuid: u32, cmd: &str, now_ms: u64) {
+        let metrics = self.commands.entry(cmd.to_string()).or_default();
+
+        match kind {
+            DispatchEventKind::Enqueued => {
+                self.timings.insert(
+                    uuid,
+                    Timing {
+                        enqueued_ms: now_ms,
+                        ..Timing::default()
+                    },
+                );
+            }
+            DispatchEventKind::Sent => {
+                metrics.sent += 1;
+                if let Some(timing) = self.timings.get_mut(&uuid) {
+                    if !timing.waited {
+                        let wait = now_ms.saturating_sub(timing.enqueued_ms);
+                        metrics.queue_wait_ms.observe(wait as f64);
+                        timing.waited = true;
+                    }
+                    timing.sent_ms.get_or_insert(now_ms);
+                }
+            }
+            DispatchEventKind::Completed | DispatchEventKind::Failed => {
+                if kind == DispatchEventKind::Completed {
+                    metrics.completed += 1;
+                } else {
+                    metrics.failed += 1;
+                }
+                if let Some(sent_ms) = self.timings.remove(&uuid).and_then(|t| t.sent_ms) {
+                    metrics
+                        .latency_ms
+                        .observe(now_ms.saturating_sub(sent_ms) as f64);
+                }
+            }
+            DispatchEventKind::Retried => {
+                metrics.retried += 1;
+                // A request re-queued after failing for good is timed from its resend
+                self.timings.entry(uuid).or_insert(Timing {
+                    enqueued_ms: now_ms,
+                    sent_ms: None,
+                    waited: true,
+                });
+            }
+            DispatchEventKind::Aborted => {
+                metrics.aborted += 1;
+                self.timings.remove(&uuid);
+            }
+        }
+    }
+
+    /// Clear the counters. Requests already pushed are still timed.
+    pub fn reset(&mut self) {
+        self.commands.clear();
+        self.batches.clear();
+    }
+
+    /// Count a batch taken from a queue
+    pub fn record_batch(&mut self, queue_type: QueueType, requests: usize, bytes: usize) {
+        let metrics = self.batches.entry(queue_type).or_default();
+        metrics.requests.observe(requests as f64);
+        metrics.bytes.observe(bytes as f64);
+    }
+
+    /// Prometheus text exposition format
+    pub fn to_prometheus(&self) -> String {
+        let mut out = String::new();
+
+        out.push_str(
+            "# HELP anycommerce_dispatch_requests_total Requests by command and outcome\n",
+        );
+        out.push_str("# TYPE anycommerce_dispatch_requests_total counter\n");
+        for (cmd, metrics) in &self.commands {
+            let outcomes = [
+                ("sent", metrics.sent),
+                ("completed", metrics.completed),
+                ("failed", metrics.failed),
+                ("retried", metrics.retried),
+                ("aborted", metrics.aborted),
+            ];
+            for (outcome, count) in outcomes {
+                let _ = writeln!(
+                    out,
+                    "anycommerce_dispatch_requests_total{{cmd=\"{}\",outcome=\"{}\"}} {}",
+                    escape(cmd),
+                    outcome,
+                    count
+                );
+            }
+        }
+
+        let commands = |histogram: fn(&CommandMetrics) -> &Histogram| {
+            self.commands
+                .iter()
+                .map(move |(cmd, m)| (format!("cmd=\"{}\"", escape(cmd)), histogram(m)))
+        };
+        write_histogram(
+            &mut out,
+            "anycommerce_dispatch_latency_ms",
+            "Time from send to response, by command",
+            commands(|m| &m.latency_ms),
+        );
+        write_histogram(
+            &mut out,
+            "anycommerce_dispatch_queue_wait_ms",
+            "Time from push to first send, by command",
+            commands(|m| &m.queue_wait_ms),
+        );
+
+        let queues = |histogram: fn(&BatchMetrics) -> &Histogram| {
+            [QueueType::Mutable, QueueType::Immutable, QueueType::Passive]
+                .into_iter()
+                .filter_map(move |queue_type| {
+                    let metrics = self.batches.get(&queue_type)?;
+                    let label = format!("queue=\"{}\"", format!("{:?}", queue_type).to_lowercase());
+                    Some((label, histogram(metrics)))
+                })
+        };
+        write_histogram(
+            &mut out,
+            "anycommerce_dispatch_batch_requests",
+            "Requests per batch, by queue",
+            queues(|m| &m.requests),
+        );
+        write_histogram(
+            &mut out,
+            "anycommerce_dispatch_batch_bytes",
+            "Serialized batch size, by queue",
+            queues(|m| &m.bytes),
+        );
+
+        out
+    }
+}
+
+fn write_histogram<'a>(
+    out: &mut String,
+    name: &str,
+    help: &str,
+    series: impl Iterator<Item = (String, &'a Histogram)>,
+) {
+    let _ = writeln!(out, "# HELP {} {}", name, help);
+    let _ = writeln!(out, "# TYPE {} histogram", name);
+
+    for (labels, histogram) in series {
+        let mut cumulative = 0;
+        for (bucket, count) in histogram.counts.iter().enumerate() {
+            cumulative += count;
+            let le = match histogram.bounds.get(bucket) {
+                Some(bound) => bound.to_string(),
+                None => "+Inf".to_string(),
+            };
+            let _ = writeln!(
+                out,
+                "{}_bucket{{{},le=\"{}\"}} {}",
+                name, labels, le, cumulative
+            );
+        }
+        let _ = writeln!(out, "{}_sum{{{}}} {}", name, labels, histogram.sum);
+        let _ = writeln!(out, "{}_count{{{}}} {}", name, labels, histogram.count);
+    }
+}
+
+/// Escape a label value for the text format
+fn escape(value: &str) -> String {
+    value
+        .replace('\\', "\\\\")
+        .replace('"', "\\\"")
+        .replace('\n', "\\n")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_histogram_and_prometheus_text() {
+        let mut histogram = Histogram::new(&[10.0, 50.0]);
+        for value in [1.0, 5.0, 20.0, 200.0] {
+            histogram.observe(value);
+        }
+        assert_eq!(histogram.counts, vec![2, 1, 1]);
+        assert_eq!(histogram.mean(), Some(56.5));
+        assert_eq!(histogram.quantile(0.5), Some(10.0));
+        assert_eq!(histogram.quantile(0.75), Some(50.0));
+        assert_eq!(histogram.quantile(1.0), Some(f64::INFINITY));
+
+        let mut metrics = DispatchMetrics::default();
+        metrics.record(DispatchEventKind::Enqueued, 1000, "cartItemAppend", 100);
+        metrics.record(DispatchEventKind::Sent, 1000, "cartItemAppend", 130);
+        metrics.record(DispatchEventKind::Completed, 1000, "cartItemAppend", 170);
+        metrics.record_batch(QueueType::Immutable, 1, 80);
+
+        let cart = &metrics.commands["cartItemAppend"];
+        assert_eq!(cart.queue_wait_ms.sum, 30.0);
+        assert_eq!(cart.latency_ms.sum, 40.0);
+        assert_eq!(cart.error_rate(), 0.0);
+
+        // Retries neither wait in the queue again nor restart the latency timer
+        metrics.record(DispatchEventKind::Enqueued, 1001, "cartDetail", 100);
+        metrics.record(DispatchEventKind::Sent, 1001, "cartDetail", 110);
+        metrics.record(DispatchEventKind::Retried, 1001, "cartDetail", 150);
+        metrics.record(DispatchEventKind::Sent, 1001, "cartDetail", 300);
+        metrics.record(DispatchEventKind::Completed, 1001, "cartDetail", 340);
+        let detail = &metrics.commands["cartDetail"];
+        assert_eq!((detail.sent, detail.retried), (2, 1));
+        assert_eq!(detail.queue_wait_ms.count, 1);
+        assert_eq!(detail.queue_wait_ms.sum, 10.0);
+        assert_eq!(detail.latency_ms.count, 1);
+        assert_eq!(detail.latency_ms.sum, 230.0);
+
+        let text = metrics.to_prometheus();
+        assert!(text.contains(
+            "anycommerce_dispatch_requests_total{cmd=\"cartItemAppend\",outcome=\"completed\"} 1\n"
+        ));
+        assert!(text.contains(
+            "anycommerce_dispatch_latency_ms_bucket{cmd=\"cartItemAppend\",le=\"50\"} 1\n"
+        ));
+        assert!(text.contains(
+            "anycommerce_dispatch_latency_ms_bucket{cmd=\"cartItemAppend\",le=\"25\"} 0\n"
+        ));
+        assert!(text.contains("anycommerce_dispatch_batch_bytes_count{queue=\"immutable\"} 1\n"));
+        assert_eq!(escape("a\"b\\c"), "a\\\"b\\\\c");
+    }
+}
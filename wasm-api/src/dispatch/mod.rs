@@ -1,6 +1,41 @@
 use wasm_bindgen::prelude::*;
 use serde::{Deserialize, Serialize};
-use std::collections::{HashMap, VecDeque};
+use std::collections::{HashMap, HashSet, VecDeque};
+
+mod batch;
+mod cache;
+mod error;
+mod events;
+mod group;
+mod metrics;
+mod persist;
+mod recorder;
+mod redact;
+mod response;
+mod retry;
+mod session;
+mod status;
+mod storage;
+mod transport;
+
+pub use batch::BatchLimits;
+pub use cache::{CachePolicy, DatapointerCache, StorageTier};
+pub use error::{check_response, ApiError, ApiMessage};
+pub use events::{DispatchEvent, DispatchEventKind, EventBus};
+pub use group::{GroupResolution, PipelineGroup};
+pub use metrics::{BatchMetrics, CommandMetrics, DispatchMetrics, Histogram};
+pub use persist::{PersistError, QueueSnapshot, RestoreReport, QUEUE_STORAGE_KEY};
+pub use recorder::{parse_recording, RecordedExchange, Recorder, ReplayTransport};
+pub use response::{CallbackInvocation, ResponseReport, UnhandledError};
+pub use retry::{is_idempotent, DeadLetter, RetryPolicy};
+pub use session::{AdminCredentials, BuyerLogin, Session};
+pub use status::RequestStatus;
+pub use storage::{MemoryStorage, StorageBackend, WebStorage};
+pub use transport::{BatchOutcome, FetchTransport, MockTransport, OutgoingBatch, Transport};
+
+use crate::commands::{self, Command, CommandError};
+use crate::utils::{epoch_now, epoch_now_ms};
+use retry::Xorshift;
 
 #[wasm_bindgen]
 #[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize)]
@@ -10,7 +45,26 @@ pub enum QueueType {
     Passive,   // Fire-and-forget, never aborted
 }
 
-#[derive(Debug, Clone, Serialize, Deserialize)]
+/// Where a request goes in the mutable queue. Higher priorities are sent first;
+/// requests of equal priority keep the order they were pushed in.
+#[wasm_bindgen]
+#[derive(
+    Debug, Clone, Copy, Default, PartialEq, Eq, PartialOrd, Ord, Hash, Serialize, Deserialize,
+)]
+pub enum Priority {
+    Low, // Prefetches and lookups nobody is waiting on
+    #[default]
+    Normal, // Everything else
+    High, // Content the user is looking at
+}
+
+impl Priority {
+    fn is_normal(&self) -> bool {
+        *self == Priority::Normal
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
 pub struct RequestTag {
     pub datapointer: String,
     #[serde(skip_serializing_if = "Option::is_none")]
@@ -19,7 +73,7 @@ pub struct RequestTag {
     pub extension: Option<String>,
 }
 
-#[derive(Debug, Clone, Serialize, Deserialize)]
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
 pub struct ApiRequest {
     #[serde(rename = "_cmd")]
     pub cmd: String,
@@ -27,13 +81,58 @@ pub struct ApiRequest {
     pub params: HashMap<String, serde_json::Value>,
     #[serde(rename = "_tag", skip_serializing_if = "Option::is_none")]
     pub tag: Option<RequestTag>,
+    #[serde(rename = "_uuid", default, skip_serializing_if = "Option::is_none")]
+    pub uuid: Option<u32>,
+    /// Only used for ordering the mutable queue. Never sent to the API.
+    #[serde(
+        rename = "_priority",
+        default,
+        skip_serializing_if = "Priority::is_normal"
+    )]
+    pub priority: Priority,
+}
+
+/// A dispatch tracked by the queue, from push until it is cleared
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct DispatchRecord {
+    pub queue_type: QueueType,
+    pub status: RequestStatus,
+    pub request: ApiRequest,
+    /// Number of times the request has been sent
+    #[serde(default)]
+    pub attempts: u32,
+    /// When the request was pushed, in seconds since the epoch
+    #[serde(default)]
+    pub queued_at: u64,
+    /// The pipeline group the request belongs to, if any
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub pipe_uuid: Option<u32>,
+    /// The identical request this one was coalesced into. It is never sent itself.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub coalesced_into: Option<u32>,
 }
 
 #[wasm_bindgen]
 pub struct DispatchQueue {
-    mutable_queue: VecDeque<ApiRequest>,
-    immutable_queue: VecDeque<ApiRequest>,
-    passive_queue: VecDeque<ApiRequest>,
+    mutable_queue: VecDeque<u32>,
+    immutable_queue: VecDeque<u32>,
+    passive_queue: VecDeque<u32>,
+    records: HashMap<u32, DispatchRecord>,
+    groups: HashMap<u32, PipelineGroup>,
+    cache: DatapointerCache,
+    session: Session,
+    events: EventBus,
+    metrics: DispatchMetrics,
+    recorder: Option<Recorder>,
+    batch_limits: BatchLimits,
+    /// Product IDs on screen. Requests for them are sent ahead of the rest.
+    visible_pids: HashSet<String>,
+    retry_policies: HashMap<QueueType, RetryPolicy>,
+    /// Failed requests waiting out their backoff, as (due time in ms, uuid)
+    scheduled_retries: Vec<(u64, u32)>,
+    dead_letters: Vec<DeadLetter>,
+    rng: Xorshift,
+    next_uuid: u32,
     endpoint: String,
 }
 
@@ -45,100 +144,2454 @@ impl DispatchQueue {
             mutable_queue: VecDeque::new(),
             immutable_queue: VecDeque::new(),
             passive_queue: VecDeque::new(),
+            records: HashMap::new(),
+            groups: HashMap::new(),
+            cache: DatapointerCache::default(),
+            session: Session::default(),
+            events: EventBus::default(),
+            metrics: DispatchMetrics::default(),
+            recorder: None,
+            batch_limits: BatchLimits::default(),
+            visible_pids: HashSet::new(),
+            retry_policies: [QueueType::Mutable, QueueType::Immutable, QueueType::Passive]
+                .into_iter()
+                .map(|q| (q, RetryPolicy::for_queue(q)))
+                .collect(),
+            scheduled_retries: Vec::new(),
+            dead_letters: Vec::new(),
+            rng: Xorshift::new(epoch_now_ms()),
+            next_uuid: 1000,
             endpoint,
         }
     }
 
-    /// Add a request to the specified queue
-    pub fn push(&mut self, queue_type: QueueType, request: JsValue) -> Result<(), JsValue> {
+    /// Add a request to the specified queue. Returns the request UUID, or
+    /// `undefined` if the request was skipped because its datapointer is still fresh.
+    /// Duplicates of a request still waiting to be sent are coalesced into it.
+    /// Requests for catalogued commands are rejected if their params are invalid.
+    pub fn push(
+        &mut self,
+        queue_type: QueueType,
+        request: JsValue,
+    ) -> Result<Option<u32>, JsValue> {
         let request: ApiRequest = serde_wasm_bindgen::from_value(request)
             .map_err(|e| JsValue::from_str(&format!("Failed to parse request: {}", e)))?;
 
-        match queue_type {
-            QueueType::Mutable => self.mutable_queue.push_back(request),
-            QueueType::Immutable => self.immutable_queue.push_back(request),
-            QueueType::Passive => self.passive_queue.push_back(request),
+        Ok(self.push_validated(queue_type, request)?)
+    }
+
+    /// Add a request with a priority. Priorities only reorder the mutable queue.
+    pub fn push_with_priority(
+        &mut self,
+        queue_type: QueueType,
+        request: JsValue,
+        priority: Priority,
+    ) -> Result<Option<u32>, JsValue> {
+        let mut request: ApiRequest = serde_wasm_bindgen::from_value(request)
+            .map_err(|e| JsValue::from_str(&format!("Failed to parse request: {}", e)))?;
+        request.priority = priority;
+
+        Ok(self.push_validated(queue_type, request)?)
+    }
+
+    /// Change the priority of a queued request, e.g. once a prefetched product
+    /// scrolls into view. Only the mutable queue is reordered; requests in the
+    /// FIFO immutable and passive queues keep their place. Returns false if the
+    /// request is not waiting in a queue.
+    pub fn set_priority(&mut self, uuid: u32, priority: Priority) -> bool {
+        let Some(queue_type) = self.records.get(&uuid).map(|r| r.queue_type) else {
+            return false;
+        };
+        let Some(position) = self.queue(queue_type).iter().position(|q| *q == uuid) else {
+            return false;
+        };
+        self.records.get_mut(&uuid).unwrap().request.priority = priority;
+
+        if queue_type == QueueType::Mutable {
+            self.queue_mut(queue_type).remove(position);
+            self.insert_queued(queue_type, uuid);
+        }
+
+        true
+    }
+
+    /// Push a catalogued command on its default queue, e.g.
+    /// `push_command("cartItemAppend", { _cartid, sku, qty })`.
+    /// The datapointer is filled in for commands whose response is kept.
+    pub fn push_command(
+        &mut self,
+        cmd: &str,
+        params: JsValue,
+        callback: Option<String>,
+        extension: Option<String>,
+    ) -> Result<Option<u32>, JsValue> {
+        let params: serde_json::Value = serde_wasm_bindgen::from_value(params)
+            .map_err(|e| JsValue::from_str(&format!("Failed to parse params: {}", e)))?;
+
+        Ok(self.push_params(cmd, params, callback, extension)?)
+    }
+
+    /// Keep session and local tier data in the browser's sessionStorage/localStorage
+    pub fn use_browser_storage(&mut self) -> bool {
+        match (WebStorage::session(), WebStorage::local()) {
+            (Some(session), Some(local)) => {
+                self.cache.set_backends(Box::new(session), Box::new(local));
+                true
+            }
+            _ => false,
         }
+    }
+
+    /// Call `listener` with each event of `kind`, e.g.
+    /// `queue.on(DispatchEventKind.Completed, e => store.loaded(e.datapointer))`.
+    /// Listeners run in a microtask after the queue call that raised the event.
+    /// Returns an ID for `off`.
+    pub fn on(&mut self, kind: DispatchEventKind, listener: js_sys::Function) -> u32 {
+        self.events
+            .subscribe(Some(kind), events::js_listener(listener))
+    }
+
+    /// Call `listener` with every event
+    pub fn on_any(&mut self, listener: js_sys::Function) -> u32 {
+        self.events.subscribe(None, events::js_listener(listener))
+    }
+
+    /// Remove a listener added with `on` or `on_any`
+    pub fn off(&mut self, id: u32) -> bool {
+        self.events.unsubscribe(id)
+    }
+
+    /// Get per-command counts and timings, error rates and batch sizes
+    pub fn get_metrics(&self) -> Result<JsValue, JsValue> {
+        self.metrics
+            .serialize(&serde_wasm_bindgen::Serializer::json_compatible())
+            .map_err(|e| JsValue::from_str(&format!("Failed to serialize metrics: {}", e)))
+    }
+
+    /// Get the metrics in the Prometheus text format
+    pub fn metrics_text(&self) -> String {
+        self.metrics.to_prometheus()
+    }
+
+    /// Start measuring from scratch. Requests already in flight are still timed.
+    pub fn reset_metrics(&mut self) {
+        self.metrics.reset();
+    }
+
+    /// Start capturing every request sent and its response or error, keeping the
+    /// newest `max_entries` (1000 if 0). Restarting discards the previous recording.
+    pub fn start_recording(&mut self, max_entries: usize) {
+        let max_entries = if max_entries == 0 { 1000 } else { max_entries };
+        self.recorder = Some(Recorder::new(max_entries));
+    }
+
+    /// Stop recording. Returns the recording as JSONL, one exchange per line.
+    pub fn stop_recording(&mut self) -> Option<String> {
+        self.recorder.take().map(|recorder| recorder.to_jsonl())
+    }
+
+    /// The recording so far as JSONL, without stopping it
+    pub fn recording(&self) -> Option<String> {
+        self.recorder.as_ref().map(|recorder| recorder.to_jsonl())
+    }
+
+    /// Get the session: session ID, client settings and who is logged in
+    pub fn get_session(&self) -> Result<JsValue, JsValue> {
+        self.session
+            .serialize(&serde_wasm_bindgen::Serializer::json_compatible())
+            .map_err(|e| JsValue::from_str(&format!("Failed to serialize session: {}", e)))
+    }
+
+    /// Replace the session, e.g. with one saved by `get_session` before a reload
+    pub fn set_session(&mut self, session: JsValue) -> Result<(), JsValue> {
+        self.session = serde_wasm_bindgen::from_value(session)
+            .map_err(|e| JsValue::from_str(&format!("Failed to parse session: {}", e)))?;
 
         Ok(())
     }
 
+    /// Set the session ID sent with every request (legacy `_app.vars._session`)
+    pub fn set_session_id(&mut self, session_id: String) {
+        self.session.session_id = Some(session_id);
+    }
+
+    /// Set the client ID, domain in focus and app version sent with every request
+    pub fn set_client(
+        &mut self,
+        client_id: Option<String>,
+        domain: Option<String>,
+        version: Option<String>,
+    ) {
+        self.session.client_id = client_id;
+        self.session.domain = domain;
+        self.session.version = version;
+    }
+
+    /// Whether an admin is logged in with a token that has not expired
+    /// (legacy `thisIsAnAdminSession`)
+    pub fn is_admin_session(&self) -> bool {
+        self.session.valid_admin(epoch_now()).is_some()
+    }
+
+    /// Whether a buyer is logged in
+    pub fn is_buyer_logged_in(&self) -> bool {
+        self.session.buyer.is_some()
+    }
+
+    /// Forget the admin and buyer logins and the login responses stored for them.
+    /// Also done when an `authAdminLogout` or `buyerLogout` request completes.
+    pub fn logout(&mut self) {
+        self.session.logout();
+        self.cache.remove("authAdminLogin");
+        self.cache.remove("appBuyerLogin");
+    }
+
+    /// Set how long responses for a command stay fresh, in seconds
+    pub fn set_ttl(&mut self, cmd: String, seconds: u64) {
+        self.cache.policy_mut().ttls.insert(cmd, seconds);
+    }
+
+    /// Whether a datapointer has data that has not expired
+    pub fn is_fresh(&self, datapointer: &str) -> bool {
+        self.cache.is_fresh(datapointer, epoch_now())
+    }
+
+    /// Remove a datapointer from every storage tier so the next push fetches it again
+    pub fn invalidate(&mut self, datapointer: &str) {
+        self.cache.remove(datapointer);
+    }
+
+    /// Cap the mutable and passive batches by request count and serialized bytes.
+    /// 0 means no limit.
+    pub fn set_batch_limits(&mut self, max_requests: usize, max_bytes: usize) {
+        let unlimited = |limit: usize| if limit == 0 { usize::MAX } else { limit };
+        self.batch_limits = BatchLimits {
+            max_requests: unlimited(max_requests),
+            max_bytes: unlimited(max_bytes),
+        };
+    }
+
+    /// Set the products currently on screen. Queued requests for them go out in
+    /// the next batch, ahead of requests for products the user cannot see.
+    pub fn set_visible_products(&mut self, pids: Vec<String>) {
+        self.visible_pids = pids.iter().map(|pid| pid.to_uppercase()).collect();
+    }
+
     /// Get the current length of a queue
     pub fn length(&self, queue_type: QueueType) -> usize {
-        match queue_type {
-            QueueType::Mutable => self.mutable_queue.len(),
-            QueueType::Immutable => self.immutable_queue.len(),
-            QueueType::Passive => self.passive_queue.len(),
-        }
+        self.queue(queue_type).len()
     }
 
-    /// Clear a specific queue (abort)
+    /// Cancel every queued and in-flight request in a queue (abort).
+    /// Returns the number of requests cancelled.
     pub fn abort(&mut self, queue_type: QueueType) -> usize {
-        let len = match queue_type {
+        match queue_type {
             QueueType::Mutable => {
-                let len = self.mutable_queue.len();
-                self.mutable_queue.clear();
-                len
+                let uuids: Vec<u32> = self
+                    .records
+                    .iter()
+                    .filter(|(_, r)| r.queue_type == QueueType::Mutable && !r.status.is_terminal())
+                    .map(|(uuid, _)| *uuid)
+                    .collect();
+
+                // Aborting one member of a group cancels the rest, so count afterwards
+                for &uuid in &uuids {
+                    self.abort_request(uuid);
+                }
+                uuids
+                    .iter()
+                    .filter(|uuid| self.status(**uuid) == Some(RequestStatus::Cancelled))
+                    .count()
             }
             QueueType::Immutable => 0, // Cannot abort immutable queue
             QueueType::Passive => 0,   // Cannot abort passive queue
+        }
+    }
+
+    /// Cancel a single queued or in-flight request. Only mutable requests can be aborted.
+    /// Aborting a member of a pipeline group aborts the whole group.
+    pub fn abort_request(&mut self, uuid: u32) -> bool {
+        let pipe_uuid = match self.records.get(&uuid) {
+            Some(record) if record.queue_type == QueueType::Mutable => record.pipe_uuid,
+            _ => return false,
         };
-        len
+
+        match pipe_uuid {
+            Some(pipe_uuid) => self.abort_group(pipe_uuid) > 0 || self.cancel(uuid),
+            None => self.cancel(uuid),
+        }
     }
 
-    /// Get all requests from a queue for batching
-    pub fn get_batch(&mut self, queue_type: QueueType) -> Result<JsValue, JsValue> {
-        let batch: Vec<ApiRequest> = match queue_type {
-            QueueType::Mutable => self.mutable_queue.drain(..).collect(),
-            QueueType::Immutable => {
-                // Immutable queue processes one at a time
-                if let Some(req) = self.immutable_queue.pop_front() {
-                    vec![req]
-                } else {
-                    vec![]
-                }
-            }
-            QueueType::Passive => self.passive_queue.drain(..).collect(),
+    /// Add requests that complete or cancel as one unit. Returns the group's pipe UUID.
+    /// `callback` is reported once every member has finished. Members are never
+    /// skipped for fresh datapointers.
+    pub fn push_group(
+        &mut self,
+        queue_type: QueueType,
+        requests: JsValue,
+        callback: Option<String>,
+        extension: Option<String>,
+    ) -> Result<u32, JsValue> {
+        let requests: Vec<ApiRequest> = serde_wasm_bindgen::from_value(requests)
+            .map_err(|e| JsValue::from_str(&format!("Failed to parse requests: {}", e)))?;
+
+        self.push_group_requests(queue_type, requests, callback, extension)
+            .ok_or_else(|| JsValue::from_str("A pipeline group needs at least one request"))
+    }
+
+    /// Cancel every unfinished member of a group. Mutable groups can always be
+    /// cancelled; immutable groups only before any member has been sent.
+    /// Returns the number of requests cancelled.
+    pub fn abort_group(&mut self, pipe_uuid: u32) -> usize {
+        let Some(group) = self.groups.get(&pipe_uuid) else {
+            return 0;
+        };
+        let members = group.members.clone();
+
+        let abortable = members.iter().all(|uuid| match self.records.get(uuid) {
+            Some(record) => match record.queue_type {
+                QueueType::Mutable => true,
+                QueueType::Immutable => record.attempts == 0,
+                QueueType::Passive => false,
+            },
+            None => true,
+        });
+        if !abortable {
+            return 0;
+        }
+
+        self.groups.remove(&pipe_uuid);
+        members
+            .into_iter()
+            .filter(|uuid| self.cancel(*uuid))
+            .count()
+    }
+
+    /// Get the UUIDs of a group's members (legacy `getUUIDsbyQIDandPipeUUID`)
+    pub fn group_uuids(&self, pipe_uuid: u32) -> Vec<u32> {
+        self.groups
+            .get(&pipe_uuid)
+            .map(|g| g.members.clone())
+            .unwrap_or_default()
+    }
+
+    /// Get the pipe UUID of the group a request belongs to
+    pub fn pipe_uuid(&self, uuid: u32) -> Option<u32> {
+        self.records.get(&uuid).and_then(|r| r.pipe_uuid)
+    }
+
+    /// Put a failed request back on its queue. Emits `Retried`, not `Enqueued`:
+    /// it is the same request, and its metrics keep their first timings.
+    pub fn retry_request(&mut self, uuid: u32) -> bool {
+        if self.transition(uuid, RequestStatus::Queued).is_err() {
+            return false;
+        }
+        let queue_type = self.records[&uuid].queue_type;
+        self.insert_queued(queue_type, uuid);
+        self.emit(DispatchEventKind::Retried, uuid, None);
+
+        true
+    }
+
+    /// Set the retry policy for a queue. `max_attempts` of 1 disables retries.
+    pub fn set_retry_policy(
+        &mut self,
+        queue_type: QueueType,
+        max_attempts: u32,
+        base_delay_ms: u32,
+        max_delay_ms: u32,
+        jitter: f64,
+    ) {
+        *self.retry_policy_mut(queue_type) = RetryPolicy {
+            max_attempts: max_attempts.max(1),
+            base_delay_ms: base_delay_ms.into(),
+            max_delay_ms: max_delay_ms.into(),
+            jitter,
+        };
+    }
+
+    /// Put failed requests whose backoff has elapsed back on their queues.
+    /// Returns the number released. `get_batch` does this automatically.
+    pub fn release_retries(&mut self) -> usize {
+        self.release_retries_at(epoch_now_ms())
+    }
+
+    /// When the next scheduled retry is due, in ms since the epoch
+    pub fn next_retry_at(&self) -> Option<f64> {
+        self.scheduled_retries
+            .iter()
+            .map(|(due, _)| *due as f64)
+            .reduce(f64::min)
+    }
+
+    /// Get the requests that ran out of attempts
+    pub fn get_dead_letters(&self) -> Result<JsValue, JsValue> {
+        self.dead_letters
+            .serialize(&serde_wasm_bindgen::Serializer::json_compatible())
+            .map_err(|e| JsValue::from_str(&format!("Failed to serialize dead letters: {}", e)))
+    }
+
+    /// Give a dead-lettered request a fresh set of attempts
+    pub fn requeue_dead_letter(&mut self, uuid: u32) -> bool {
+        let Some(index) = self.dead_letters.iter().position(|d| d.uuid == uuid) else {
+            return false;
         };
+        if !self.retry_request(uuid) {
+            return false;
+        }
+        self.dead_letters.remove(index);
+        if let Some(record) = self.records.get_mut(&uuid) {
+            record.attempts = 0;
+        }
+
+        true
+    }
+
+    /// Mark an in-flight request as failed (e.g. the HTTP call itself failed)
+    pub fn fail_request(&mut self, uuid: u32) -> bool {
+        self.transition(uuid, RequestStatus::Error).is_ok()
+    }
+
+    /// Get the status of a request
+    pub fn status(&self, uuid: u32) -> Option<RequestStatus> {
+        self.records.get(&uuid).map(|r| r.status)
+    }
+
+    /// Get the queue a request was pushed to (legacy `whichQAmIFrom`)
+    pub fn which_queue(&self, uuid: u32) -> Option<QueueType> {
+        self.records.get(&uuid).map(|r| r.queue_type)
+    }
+
+    /// Drop completed and cancelled requests. Returns the number removed.
+    pub fn clear_finished(&mut self) -> usize {
+        let before = self.records.len();
+        self.records.retain(|_, r| !r.status.is_terminal());
+        before - self.records.len()
+    }
+
+    /// Get the next batch of requests from a queue. Requests over the batch limits
+    /// stay queued; call again while `length` is non-zero to send them.
+    pub fn get_batch(&mut self, queue_type: QueueType) -> Result<JsValue, JsValue> {
+        let batch = self.take_batch(queue_type);
 
         serde_wasm_bindgen::to_value(&batch)
             .map_err(|e| JsValue::from_str(&format!("Failed to serialize batch: {}", e)))
     }
 
+    /// Process an API batch response, storing each payload under its datapointer.
+    /// Returns the callbacks that should be fired, in response order, and any
+    /// errors that no callback handles.
+    pub fn process_responses(&mut self, response: JsValue) -> Result<JsValue, JsValue> {
+        let response: serde_json::Value = serde_wasm_bindgen::from_value(response)
+            .map_err(|e| JsValue::from_str(&format!("Failed to parse response: {}", e)))?;
+
+        let report = self.handle_response(response);
+
+        report
+            .serialize(&serde_wasm_bindgen::Serializer::json_compatible())
+            .map_err(|e| JsValue::from_str(&format!("Failed to serialize report: {}", e)))
+    }
+
+    /// Fail a batch that never got a response (network error, HTTP error status).
+    /// Returns the same report as `process_responses`.
+    pub fn fail_batch(&mut self, uuids: Vec<u32>, message: String) -> Result<JsValue, JsValue> {
+        let report = self.fail_requests(&uuids, ApiError::Transport { message });
+
+        report
+            .serialize(&serde_wasm_bindgen::Serializer::json_compatible())
+            .map_err(|e| JsValue::from_str(&format!("Failed to serialize report: {}", e)))
+    }
+
+    /// Take the next batch from a queue and send it with `fetch`. Resolves to the
+    /// outcome to hand to `finish_batch`, or to `undefined` if there was nothing
+    /// to send. Never rejects: a failed send is part of the outcome.
+    pub fn fetch_batch(&mut self, queue_type: QueueType) -> js_sys::Promise {
+        let batch = self.take_batch(queue_type);
+        if batch.is_empty() {
+            return js_sys::Promise::resolve(&JsValue::UNDEFINED);
+        }
+        let uuids: Vec<u32> = batch.iter().filter_map(|r| r.uuid).collect();
+        let outgoing = self.outgoing_batch(&batch);
+
+        wasm_bindgen_futures::future_to_promise(async move {
+            let outcome = BatchOutcome::new(uuids, FetchTransport.send(&outgoing).await);
+
+            outcome
+                .serialize(&serde_wasm_bindgen::Serializer::json_compatible())
+                .map_err(|e| JsValue::from_str(&format!("Failed to serialize outcome: {}", e)))
+        })
+    }
+
+    /// Handle the outcome of `fetch_batch` as `dispatch` does. Returns the same
+    /// report as `process_responses`.
+    pub fn finish_batch(&mut self, outcome: JsValue) -> Result<JsValue, JsValue> {
+        let outcome: BatchOutcome = serde_wasm_bindgen::from_value(outcome)
+            .map_err(|e| JsValue::from_str(&format!("Failed to parse outcome: {}", e)))?;
+
+        let (uuids, result) = outcome.into_parts();
+        let report = self.complete_batch(&uuids, result);
+
+        report
+            .serialize(&serde_wasm_bindgen::Serializer::json_compatible())
+            .map_err(|e| JsValue::from_str(&format!("Failed to serialize report: {}", e)))
+    }
+
+    /// Get the response data stored under a datapointer
+    pub fn get_data(&mut self, datapointer: &str) -> Result<JsValue, JsValue> {
+        let data = self.cache.get(datapointer, epoch_now()).ok_or_else(|| {
+            JsValue::from_str(&format!("No data for datapointer {}", datapointer))
+        })?;
+
+        data.serialize(&serde_wasm_bindgen::Serializer::json_compatible())
+            .map_err(|e| JsValue::from_str(&format!("Failed to serialize data: {}", e)))
+    }
+
+    /// Save the queues to localStorage so queued requests survive going offline
+    /// or a reload. Call again whenever the queues change. Requests carrying
+    /// credentials or payment details are never saved.
+    pub fn persist(&self) -> Result<(), JsValue> {
+        let mut storage = WebStorage::local()
+            .ok_or_else(|| JsValue::from_str("localStorage is not available"))?;
+
+        Ok(self.save_to(&mut storage, epoch_now())?)
+    }
+
+    /// Restore the queues saved by `persist`. Mutable requests older than
+    /// `max_mutable_age` seconds are dropped. Returns a `RestoreReport`.
+    pub fn restore(&mut self, max_mutable_age: u32) -> Result<JsValue, JsValue> {
+        let mut storage = WebStorage::local()
+            .ok_or_else(|| JsValue::from_str("localStorage is not available"))?;
+        let report = self.restore_from(&mut storage, epoch_now(), max_mutable_age.into())?;
+
+        report
+            .serialize(&serde_wasm_bindgen::Serializer::json_compatible())
+            .map_err(|e| JsValue::from_str(&format!("Failed to serialize report: {}", e)))
+    }
+
     /// Get the API endpoint
     pub fn get_endpoint(&self) -> String {
         self.endpoint.clone()
     }
 
+    /// Whether an immutable request is in flight. The lock is released only when
+    /// every in-flight immutable request has been acked through `process_responses`
+    /// or `fail_batch`; a request that gets no response must be failed explicitly.
+    /// `dispatch` fails requests the response leaves unanswered itself.
+    pub fn is_immutable_locked(&self) -> bool {
+        self.records
+            .values()
+            .any(|r| r.queue_type == QueueType::Immutable && r.status == RequestStatus::Requesting)
+    }
+
     /// Check if any queue has pending requests
     pub fn has_pending(&self) -> bool {
         !self.mutable_queue.is_empty()
             || !self.immutable_queue.is_empty()
             || !self.passive_queue.is_empty()
+            || !self.scheduled_retries.is_empty()
     }
 }
 
-#[cfg(test)]
-mod tests {
-    use super::*;
+impl DispatchQueue {
+    /// `push_request`, rejecting requests for catalogued commands with invalid params
+    pub fn push_validated(
+        &mut self,
+        queue_type: QueueType,
+        request: ApiRequest,
+    ) -> Result<Option<u32>, CommandError> {
+        commands::validate_request(&request)?;
 
-    #[test]
-    fn test_queue_push_and_length() {
-        let mut queue = DispatchQueue::new("/jsonapi/".to_string());
+        Ok(self.push_request(queue_type, request))
+    }
 
-        let mut params = HashMap::new();
-        params.insert("pid".to_string(), serde_json::json!("TEST"));
+    /// Push a catalogued command, given its params as JSON, on its default queue
+    pub fn push_params(
+        &mut self,
+        cmd: &str,
+        params: serde_json::Value,
+        callback: Option<String>,
+        extension: Option<String>,
+    ) -> Result<Option<u32>, CommandError> {
+        let (queue_type, request) = commands::build_request(cmd, params, callback, extension)?;
 
-        let request = ApiRequest {
-            cmd: "appProductGet".to_string(),
-            params,
-            tag: None,
+        Ok(self.push_request(queue_type, request))
+    }
+
+    /// Add a typed request to the specified queue. Returns the request UUID, or
+    /// `None` if it was skipped because its datapointer is still fresh.
+    /// Immutable requests are never skipped.
+    /// A request identical to one still waiting in the mutable or passive queue is
+    /// coalesced into it: only one is sent, and both are notified of the response.
+    /// The one sent moves up to the higher of the two priorities.
+    pub fn push_request(&mut self, queue_type: QueueType, request: ApiRequest) -> Option<u32> {
+        if queue_type != QueueType::Immutable {
+            if let Some(tag) = &request.tag {
+                if !tag.datapointer.is_empty() && self.cache.is_fresh(&tag.datapointer, epoch_now())
+                {
+                    return None;
+                }
+            }
+        }
+
+        let duplicate_of = self.find_duplicate(queue_type, &request);
+        let uuid = self.track(queue_type, request, None);
+        match duplicate_of {
+            Some(primary) => {
+                let record = self.records.get_mut(&uuid).unwrap();
+                record.coalesced_into = Some(primary);
+                let priority = record.request.priority;
+                if priority > self.records[&primary].request.priority {
+                    self.set_priority(primary, priority);
+                }
+            }
+            None => self.insert_queued(queue_type, uuid),
+        }
+
+        Some(uuid)
+    }
+
+    /// Validate a typed command and push it on its default queue
+    pub fn push_typed<C: Command>(
+        &mut self,
+        command: &C,
+        callback: Option<String>,
+    ) -> Result<Option<u32>, CommandError> {
+        let request = command.to_request(callback, None)?;
+
+        Ok(self.push_request(C::QUEUE, request))
+    }
+
+    /// The typed response stored for a command, if it is still fresh
+    pub fn typed_data<C: Command>(
+        &mut self,
+        command: &C,
+    ) -> Option<Result<C::Response, CommandError>> {
+        let datapointer = command.datapointer()?;
+
+        self.data(&datapointer).map(C::parse_response)
+    }
+
+    /// Add typed requests as a pipeline group. Returns the group's pipe UUID,
+    /// or `None` if there are no requests.
+    pub fn push_group_requests(
+        &mut self,
+        queue_type: QueueType,
+        requests: Vec<ApiRequest>,
+        callback: Option<String>,
+        extension: Option<String>,
+    ) -> Option<u32> {
+        if requests.is_empty() {
+            return None;
+        }
+
+        let pipe_uuid = self.next_uuid;
+        self.next_uuid += 1;
+        let members = requests
+            .into_iter()
+            .map(|request| self.enqueue(queue_type, request, Some(pipe_uuid)))
+            .collect();
+
+        self.groups.insert(
+            pipe_uuid,
+            PipelineGroup {
+                members,
+                callback,
+                extension,
+                error: None,
+            },
+        );
+
+        Some(pipe_uuid)
+    }
+
+    /// Take the next batch from a queue. Each request moves to `Requesting`
+    /// until its response is handled. Retries that are due are released first.
+    /// The immutable queue yields nothing until the previous immutable batch is acked.
+    /// Mutable and passive batches are cut at the batch limits, visible products first.
+    pub fn take_batch(&mut self, queue_type: QueueType) -> Vec<ApiRequest> {
+        self.release_retries_at(epoch_now_ms());
+
+        let uuids: Vec<u32> = match queue_type {
+            QueueType::Mutable | QueueType::Passive => self.take_chunk(queue_type),
+            QueueType::Immutable
+                if self.is_immutable_locked() || self.has_scheduled_retry(QueueType::Immutable) =>
+            {
+                // Immutable requests run in order, so nothing is sent while one is
+                // in flight or waits to be retried
+                vec![]
+            }
+            QueueType::Immutable => {
+                // Immutable queue processes one at a time, except that a group goes together
+                let front = self.immutable_queue.front();
+                match front.and_then(|uuid| self.records[uuid].pipe_uuid) {
+                    Some(pipe_uuid) => {
+                        let (group, rest): (VecDeque<u32>, VecDeque<u32>) = self
+                            .immutable_queue
+                            .drain(..)
+                            .partition(|uuid| self.records[uuid].pipe_uuid == Some(pipe_uuid));
+                        self.immutable_queue = rest;
+                        group.into()
+                    }
+                    None => self.immutable_queue.pop_front().into_iter().collect(),
+                }
+            }
         };
 
-        let js_request = serde_wasm_bindgen::to_value(&request).unwrap();
-        queue.push(QueueType::Mutable, js_request).unwrap();
+        let sent: Vec<u32> = uuids
+            .into_iter()
+            .filter(|uuid| self.transition(*uuid, RequestStatus::Requesting).is_ok())
+            .collect();
+        for &uuid in &sent {
+            self.emit(DispatchEventKind::Sent, uuid, None);
+        }
 
-        assert_eq!(queue.length(QueueType::Mutable), 1);
-        assert_eq!(queue.length(QueueType::Immutable), 0);
+        // Coalesced requests ride along with the request they were merged into
+        let followers: Vec<u32> = sent.iter().flat_map(|uuid| self.followers(*uuid)).collect();
+        for uuid in followers {
+            if self.transition(uuid, RequestStatus::Requesting).is_ok() {
+                self.records.get_mut(&uuid).unwrap().attempts += 1;
+                self.emit(DispatchEventKind::Sent, uuid, None);
+            }
+        }
+
+        let batch: Vec<ApiRequest> = sent
+            .iter()
+            .map(|uuid| {
+                let record = self.records.get_mut(uuid).unwrap();
+                record.attempts += 1;
+                ApiRequest {
+                    priority: Priority::default(),
+                    ..record.request.clone()
+                }
+            })
+            .collect();
+        if let Some(recorder) = self.recorder.as_mut() {
+            let now_ms = epoch_now_ms();
+            for request in &batch {
+                recorder.sent(queue_type, request, now_ms);
+            }
+        }
+        if !batch.is_empty() {
+            let bytes = serde_json::to_vec(&batch).map_or(0, |json| json.len());
+            self.metrics.record_batch(queue_type, batch.len(), bytes);
+        }
+
+        batch
+    }
+
+    /// Put retries due at or before `now_ms` back on their queues. Immutable
+    /// retries go to the front so they are still sent before later requests.
+    pub fn release_retries_at(&mut self, now_ms: u64) -> usize {
+        let mut due: Vec<(u64, u32)> = self
+            .scheduled_retries
+            .iter()
+            .copied()
+            .filter(|(at, _)| *at <= now_ms)
+            .collect();
+        self.scheduled_retries.retain(|(at, _)| *at > now_ms);
+        due.sort();
+
+        for &(_, uuid) in &due {
+            match self.records[&uuid].queue_type {
+                QueueType::Immutable => self.immutable_queue.push_front(uuid),
+                queue_type => self.insert_queued(queue_type, uuid),
+            }
+        }
+
+        due.len()
+    }
+
+    /// Match each command response back to its in-flight request by UUID and
+    /// store the payload under the request's datapointer (legacy `handleResponse`).
+    /// Requests coalesced into the answered request get the same response.
+    /// Responses with errors are routed to the failing request instead of being stored.
+    /// Responses for unknown or cancelled requests are ignored.
+    pub fn handle_response(&mut self, response: serde_json::Value) -> ResponseReport {
+        if error::is_pipe_error(&response) {
+            let pipe_error = match error::check_response("pipeline", &response) {
+                Err(error) => error,
+                Ok(_) => ApiError::Api {
+                    code: String::new(),
+                    errtype: "apierr".to_string(),
+                    message: "The API returned an error for the request".to_string(),
+                },
+            };
+            // errid 10 is only ever an expired admin token
+            let session_expired = error::is_token_expired(&response);
+            if session_expired {
+                self.expire_admin();
+            }
+            let mut report = match response::response_uuid(&response) {
+                Some(uuid) if self.status(uuid) == Some(RequestStatus::Requesting) => {
+                    self.fail_requests(&[uuid], pipe_error)
+                }
+                // An error for a whole pipeline group (legacy `getQIDFromPipeUUID`)
+                Some(pipe_uuid) if self.in_flight_members(pipe_uuid).next().is_some() => {
+                    let members: Vec<u32> = self.in_flight_members(pipe_uuid).collect();
+                    self.fail_requests(&members, pipe_error)
+                }
+                _ => ResponseReport {
+                    unhandled: vec![UnhandledError {
+                        uuid: None,
+                        error: pipe_error,
+                    }],
+                    ..ResponseReport::default()
+                },
+            };
+            report.session_expired = session_expired;
+
+            return report;
+        }
+
+        let mut report = ResponseReport::default();
+
+        for mut entry in response::command_responses(response) {
+            let Some(uuid) = response::response_uuid(&entry) else {
+                continue;
+            };
+            if let Some(recorder) = self.recorder.as_mut() {
+                recorder.answered(uuid, &entry);
+            }
+            // The sender may have been aborted while requests coalesced into it were not
+            let mut answered: Vec<u32> = self
+                .followers(uuid)
+                .into_iter()
+                .filter(|f| self.status(*f) == Some(RequestStatus::Requesting))
+                .collect();
+            if self.status(uuid) == Some(RequestStatus::Requesting) {
+                answered.insert(0, uuid);
+            }
+            let Some(first) = answered.first() else {
+                continue;
+            };
+            let cmd = self.records[first].request.cmd.clone();
+
+            match error::check_response(&cmd, &entry) {
+                Ok(messages) => {
+                    if let Some(map) = entry.as_object_mut() {
+                        map.remove("_rtag");
+                    }
+                    for uuid in answered {
+                        self.complete(uuid, &entry, &messages, &mut report);
+                    }
+                }
+                Err(error) => {
+                    if error::is_token_expired(&entry) {
+                        self.expire_admin();
+                        report.session_expired = true;
+                    }
+                    let failed = self.fail_requests(&[uuid], error);
+                    report.extend(failed);
+                }
+            }
+        }
+        self.resolve_groups(&mut report);
+
+        report
+    }
+
+    /// Build the wire request for a batch taken from a queue
+    pub fn outgoing_batch(&self, batch: &[ApiRequest]) -> OutgoingBatch {
+        OutgoingBatch {
+            endpoint: self.endpoint.clone(),
+            headers: [("Content-Type".to_string(), "application/json".to_string())]
+                .into_iter()
+                .chain(self.session.headers(epoch_now()))
+                .collect(),
+            body: serde_json::to_value(batch).unwrap_or_default(),
+        }
+    }
+
+    /// Take a batch from a queue, send it and handle the response.
+    /// JS drives the same cycle through `fetch_batch` and `finish_batch`, or with
+    /// its own transport through `get_batch`, `process_responses` and `fail_batch`.
+    pub async fn dispatch<T: Transport>(
+        &mut self,
+        queue_type: QueueType,
+        transport: &T,
+    ) -> ResponseReport {
+        let batch = self.take_batch(queue_type);
+        if batch.is_empty() {
+            return ResponseReport::default();
+        }
+        let uuids: Vec<u32> = batch.iter().filter_map(|r| r.uuid).collect();
+
+        let result = transport.send(&self.outgoing_batch(&batch)).await;
+        self.complete_batch(&uuids, result)
+    }
+
+    /// Handle the response to a sent batch, or the error it failed with.
+    /// A failed send fails every request in the batch with `ApiError::Transport`.
+    /// Requests the response leaves unanswered fail too: with the pipe's error if
+    /// it names no request, otherwise with `ApiError::Transport`.
+    pub fn complete_batch(
+        &mut self,
+        uuids: &[u32],
+        result: Result<serde_json::Value, ApiError>,
+    ) -> ResponseReport {
+        match result {
+            Ok(response) => {
+                let mut report = self.handle_response(response);
+                let unanswered: Vec<u32> = uuids
+                    .iter()
+                    .copied()
+                    .filter(|uuid| self.status(*uuid) == Some(RequestStatus::Requesting))
+                    .collect();
+                if unanswered.is_empty() {
+                    return report;
+                }
+
+                // A pipe error without a UUID is the answer for the whole batch
+                let error = match report.unhandled.iter().position(|u| u.uuid.is_none()) {
+                    Some(index) => report.unhandled.remove(index).error,
+                    None => ApiError::Transport {
+                        message: "The response did not include the request".to_string(),
+                    },
+                };
+                report.extend(self.fail_requests(&unanswered, error));
+
+                report
+            }
+            Err(error) => self.fail_requests(uuids, error),
+        }
+    }
+
+    /// Mark in-flight requests as failed and route the error to each request's
+    /// callback (legacy `handleErrorByUUID`). Used for errors on the pipe and for
+    /// requests that never got a response.
+    /// Idempotent requests that failed with a retryable error are scheduled to be
+    /// sent again per their queue's retry policy instead. Requests that run out of
+    /// attempts are added to the dead-letter list.
+    pub fn fail_requests(&mut self, uuids: &[u32], error: ApiError) -> ResponseReport {
+        let mut report = ResponseReport::default();
+
+        for &uuid in uuids {
+            let followers = self.followers(uuid);
+            self.fail_one(uuid, &error, &mut report);
+
+            // Coalesced requests share the outcome of the request they were merged into
+            let retrying = self.status(uuid) == Some(RequestStatus::Queued);
+            for follower in followers {
+                if self.transition(follower, RequestStatus::Error).is_err() {
+                    continue;
+                }
+                if retrying {
+                    let _ = self.transition(follower, RequestStatus::Queued);
+                    self.emit(DispatchEventKind::Retried, follower, Some(&error));
+                } else {
+                    self.route_error(follower, &error, &mut report);
+                }
+            }
+        }
+        self.resolve_groups(&mut report);
+
+        report
+    }
+
+    /// Get fresh response data stored under a datapointer
+    pub fn data(&mut self, datapointer: &str) -> Option<&serde_json::Value> {
+        self.cache.get(datapointer, epoch_now())
+    }
+
+    /// The batch size limits applied when taking a batch
+    pub fn batch_limits_mut(&mut self) -> &mut BatchLimits {
+        &mut self.batch_limits
+    }
+
+    /// Call `callback` for events of `kind`, or for every event if `kind` is `None`.
+    /// Native listeners are called synchronously, while the event is raised.
+    pub fn subscribe(
+        &mut self,
+        kind: Option<DispatchEventKind>,
+        callback: impl Fn(&DispatchEvent) + 'static,
+    ) -> u32 {
+        self.events.subscribe(kind, callback)
+    }
+
+    pub fn metrics(&self) -> &DispatchMetrics {
+        &self.metrics
+    }
+
+    pub fn recorder(&self) -> Option<&Recorder> {
+        self.recorder.as_ref()
+    }
+
+    pub fn session(&self) -> &Session {
+        &self.session
+    }
+
+    pub fn session_mut(&mut self) -> &mut Session {
+        &mut self.session
+    }
+
+    pub fn cache_mut(&mut self) -> &mut DatapointerCache {
+        &mut self.cache
+    }
+
+    /// The retry policy for a queue
+    pub fn retry_policy_mut(&mut self, queue_type: QueueType) -> &mut RetryPolicy {
+        self.retry_policies
+            .entry(queue_type)
+            .or_insert_with(|| RetryPolicy::for_queue(queue_type))
+    }
+
+    /// Requests that ran out of attempts, oldest first
+    pub fn dead_letters(&self) -> &[DeadLetter] {
+        &self.dead_letters
+    }
+
+    /// Get a tracked request by UUID
+    pub fn record(&self, uuid: u32) -> Option<&DispatchRecord> {
+        self.records.get(&uuid)
+    }
+
+    /// The immutable requests holding the in-flight lock
+    pub fn immutable_in_flight(&self) -> Vec<u32> {
+        let mut uuids: Vec<u32> = self
+            .records
+            .iter()
+            .filter(|(_, r)| {
+                r.queue_type == QueueType::Immutable && r.status == RequestStatus::Requesting
+            })
+            .map(|(uuid, _)| *uuid)
+            .collect();
+        uuids.sort();
+
+        uuids
+    }
+
+    /// Number of requests that have been sent but not yet answered
+    pub fn in_flight_count(&self) -> usize {
+        self.records
+            .values()
+            .filter(|r| r.status == RequestStatus::Requesting)
+            .count()
+    }
+
+    /// Count something that happened to a request and tell listeners about it
+    fn emit(&mut self, kind: DispatchEventKind, uuid: u32, error: Option<&ApiError>) {
+        let Some(record) = self.records.get(&uuid) else {
+            return;
+        };
+        self.metrics
+            .record(kind, uuid, &record.request.cmd, epoch_now_ms());
+        if self.events.is_empty() {
+            return;
+        }
+
+        self.events.emit(DispatchEvent {
+            kind,
+            uuid,
+            queue_type: record.queue_type,
+            cmd: record.request.cmd.clone(),
+            datapointer: record
+                .request
+                .tag
+                .as_ref()
+                .map(|t| t.datapointer.clone())
+                .filter(|d| !d.is_empty()),
+            error: error.cloned(),
+        });
+    }
+
+    /// Move a request to a new status, enforcing the status state machine
+    fn transition(&mut self, uuid: u32, next: RequestStatus) -> Result<(), String> {
+        let record = self
+            .records
+            .get_mut(&uuid)
+            .ok_or_else(|| format!("Request {} not found", uuid))?;
+
+        if !record.status.can_transition_to(next) {
+            return Err(format!(
+                "Request {} cannot move from {:?} to {:?}",
+                uuid, record.status, next
+            ));
+        }
+        record.status = next;
+
+        Ok(())
+    }
+
+    /// Assign a UUID to a request and add it to its queue
+    fn enqueue(
+        &mut self,
+        queue_type: QueueType,
+        request: ApiRequest,
+        pipe_uuid: Option<u32>,
+    ) -> u32 {
+        let uuid = self.track(queue_type, request, pipe_uuid);
+        self.insert_queued(queue_type, uuid);
+
+        uuid
+    }
+
+    /// Add a queued request to its queue. The mutable queue is kept in priority
+    /// order, after every request of the same or higher priority; the others are FIFO.
+    fn insert_queued(&mut self, queue_type: QueueType, uuid: u32) {
+        if queue_type != QueueType::Mutable {
+            self.queue_mut(queue_type).push_back(uuid);
+            return;
+        }
+
+        let priority = self.records[&uuid].request.priority;
+        let position = self
+            .mutable_queue
+            .iter()
+            .position(|queued| self.records[queued].request.priority < priority)
+            .unwrap_or(self.mutable_queue.len());
+        self.mutable_queue.insert(position, uuid);
+    }
+
+    /// Assign a UUID to a request and start tracking it as queued
+    fn track(
+        &mut self,
+        queue_type: QueueType,
+        mut request: ApiRequest,
+        pipe_uuid: Option<u32>,
+    ) -> u32 {
+        let uuid = self.next_uuid;
+        self.next_uuid += 1;
+        request.uuid = Some(uuid);
+
+        self.records.insert(
+            uuid,
+            DispatchRecord {
+                queue_type,
+                status: RequestStatus::Queued,
+                request,
+                attempts: 0,
+                queued_at: epoch_now(),
+                pipe_uuid,
+                coalesced_into: None,
+            },
+        );
+        self.emit(DispatchEventKind::Enqueued, uuid, None);
+
+        uuid
+    }
+
+    /// Take as many requests from the front of a queue as fit in one batch, after
+    /// moving requests for visible products to the front. The rest keep their order.
+    fn take_chunk(&mut self, queue_type: QueueType) -> Vec<u32> {
+        let mut chunk: Vec<u32> = self.queue(queue_type).iter().copied().collect();
+        chunk.sort_by_key(|uuid| !self.is_visible(*uuid));
+
+        let sizes = chunk.iter().map(|uuid| {
+            serde_json::to_vec(&self.records[uuid].request).map_or(0, |json| json.len())
+        });
+        chunk.truncate(self.batch_limits.chunk_len(sizes));
+        self.queue_mut(queue_type)
+            .retain(|uuid| !chunk.contains(uuid));
+
+        chunk
+    }
+
+    /// Whether a request is for a product on screen
+    fn is_visible(&self, uuid: u32) -> bool {
+        self.records[&uuid]
+            .request
+            .params
+            .get("pid")
+            .and_then(|pid| pid.as_str())
+            .is_some_and(|pid| self.visible_pids.contains(&pid.to_uppercase()))
+    }
+
+    /// A request waiting in the mutable or passive queue with the same command and params
+    fn find_duplicate(&self, queue_type: QueueType, request: &ApiRequest) -> Option<u32> {
+        if queue_type == QueueType::Immutable {
+            return None;
+        }
+
+        self.queue(queue_type).iter().copied().find(|uuid| {
+            let record = &self.records[uuid];
+            record.pipe_uuid.is_none()
+                && record.request.cmd == request.cmd
+                && record.request.params == request.params
+        })
+    }
+
+    /// Requests coalesced into `uuid`, oldest first
+    fn followers(&self, uuid: u32) -> Vec<u32> {
+        let mut followers: Vec<u32> = self
+            .records
+            .iter()
+            .filter(|(_, r)| r.coalesced_into == Some(uuid))
+            .map(|(follower, _)| *follower)
+            .collect();
+        followers.sort();
+
+        followers
+    }
+
+    /// Mark a request completed, store its response and report its callback
+    fn complete(
+        &mut self,
+        uuid: u32,
+        entry: &serde_json::Value,
+        messages: &[ApiMessage],
+        report: &mut ResponseReport,
+    ) {
+        if self.transition(uuid, RequestStatus::Completed).is_err() {
+            return;
+        }
+        self.emit(DispatchEventKind::Completed, uuid, None);
+        self.update_session(uuid, entry);
+        let request = &self.records[&uuid].request;
+        let cmd = request.cmd.clone();
+        let Some(tag) = request.tag.clone() else {
+            return;
+        };
+
+        if !tag.datapointer.is_empty() {
+            self.cache
+                .insert(&cmd, &tag.datapointer, entry.clone(), epoch_now());
+        }
+        if let Some(callback) = tag.callback {
+            report.callbacks.push(CallbackInvocation {
+                uuid,
+                cmd,
+                callback,
+                extension: tag.extension,
+                datapointer: Some(tag.datapointer).filter(|d| !d.is_empty()),
+                error: None,
+                messages: messages.to_vec(),
+            });
+        }
+    }
+
+    /// Fail one in-flight request: schedule a retry, or dead-letter it and report the error
+    fn fail_one(&mut self, uuid: u32, error: &ApiError, report: &mut ResponseReport) {
+        if self.transition(uuid, RequestStatus::Error).is_err() {
+            return;
+        }
+        if let Some(recorder) = self.recorder.as_mut() {
+            recorder.failed(uuid, error);
+        }
+
+        if retry::is_retryable(error) {
+            let record = &self.records[&uuid];
+            let (queue_type, attempts) = (record.queue_type, record.attempts);
+            let idempotent = retry::is_idempotent(&record.request.cmd);
+            let policy = *self.retry_policy_mut(queue_type);
+
+            if idempotent && attempts < policy.max_attempts {
+                let due = epoch_now_ms() + policy.delay_ms(attempts, &mut self.rng);
+                let _ = self.transition(uuid, RequestStatus::Queued);
+                self.scheduled_retries.push((due, uuid));
+                report.retrying.push(uuid);
+                self.emit(DispatchEventKind::Retried, uuid, Some(error));
+                return;
+            }
+
+            self.dead_letters.push(DeadLetter::new(
+                uuid,
+                queue_type,
+                &self.records[&uuid].request,
+                attempts,
+                error.clone(),
+            ));
+        }
+
+        // The rest of the group is not sent once a member has failed for good
+        if let Some(pipe_uuid) = self.records[&uuid].pipe_uuid {
+            let queued: Vec<u32> = match self.groups.get_mut(&pipe_uuid) {
+                Some(group) => {
+                    group.error.get_or_insert_with(|| error.clone());
+                    group.members.clone()
+                }
+                None => vec![],
+            };
+            for member in queued {
+                if self.status(member) == Some(RequestStatus::Queued) {
+                    self.cancel(member);
+                }
+            }
+        }
+
+        self.emit(DispatchEventKind::Failed, uuid, Some(error));
+        self.route_error(uuid, error, report);
+    }
+
+    /// Report an error to a request's callback, or as unhandled if it has none
+    fn route_error(&self, uuid: u32, error: &ApiError, report: &mut ResponseReport) {
+        let request = &self.records[&uuid].request;
+
+        match request.tag.as_ref().and_then(|t| t.callback.clone()) {
+            Some(callback) => {
+                let tag = request.tag.as_ref();
+                report.callbacks.push(CallbackInvocation {
+                    uuid,
+                    cmd: request.cmd.clone(),
+                    callback,
+                    extension: tag.and_then(|t| t.extension.clone()),
+                    datapointer: tag.map(|t| t.datapointer.clone()).filter(|d| !d.is_empty()),
+                    error: Some(error.clone()),
+                    messages: vec![],
+                });
+            }
+            None => report.unhandled.push(UnhandledError {
+                uuid: Some(uuid),
+                error: error.clone(),
+            }),
+        }
+    }
+
+    /// Pick up login state from a completed login or logout (legacy
+    /// `handleResponse_authAdminLogin`)
+    fn update_session(&mut self, uuid: u32, entry: &serde_json::Value) {
+        let request = &self.records[&uuid].request;
+        match request.cmd.as_str() {
+            "authAdminLogin" | "authNewAccountCreate" => {
+                let expires_at = epoch_now() + self.cache.policy().ttl("authAdminLogin");
+                self.session.admin_login(entry, expires_at);
+            }
+            "appBuyerLogin" => {
+                let login = request
+                    .params
+                    .get("login")
+                    .and_then(|login| login.as_str())
+                    .unwrap_or_default()
+                    .to_string();
+                self.session.buyer_login(&login, entry);
+            }
+            "authAdminLogout" | "buyerLogout" => self.logout(),
+            _ => {}
+        }
+    }
+
+    /// Drop admin credentials whose token the API has rejected
+    fn expire_admin(&mut self) {
+        self.session.admin = None;
+        self.cache.remove("authAdminLogin");
+    }
+
+    /// Cancel a queued or in-flight request and take it out of its queue.
+    /// A queued request's place is taken by the first request coalesced into it.
+    fn cancel(&mut self, uuid: u32) -> bool {
+        if self.transition(uuid, RequestStatus::Cancelled).is_err() {
+            return false;
+        }
+        self.emit(DispatchEventKind::Aborted, uuid, None);
+        if let Some(recorder) = self.recorder.as_mut() {
+            recorder.cancelled(uuid);
+        }
+        let queue_type = self.records[&uuid].queue_type;
+
+        let followers = self.followers(uuid);
+        let position = self.queue(queue_type).iter().position(|q| *q == uuid);
+        if let (Some(position), Some(&promoted)) = (position, followers.first()) {
+            self.queue_mut(queue_type)[position] = promoted;
+            for follower in &followers {
+                let record = self.records.get_mut(follower).unwrap();
+                record.coalesced_into = Some(promoted).filter(|p| p != follower);
+            }
+        }
+        self.queue_mut(queue_type).retain(|queued| *queued != uuid);
+        self.scheduled_retries
+            .retain(|(_, scheduled)| *scheduled != uuid);
+
+        true
+    }
+
+    fn in_flight_members(&self, pipe_uuid: u32) -> impl Iterator<Item = u32> + '_ {
+        self.group_uuids(pipe_uuid)
+            .into_iter()
+            .filter(|uuid| self.status(*uuid) == Some(RequestStatus::Requesting))
+    }
+
+    /// Report and forget groups whose members have all finished
+    fn resolve_groups(&mut self, report: &mut ResponseReport) {
+        let mut resolved: Vec<u32> = self
+            .groups
+            .iter()
+            .filter(|(_, group)| {
+                group.members.iter().all(|uuid| {
+                    self.records
+                        .get(uuid)
+                        .is_none_or(|r| r.status.is_terminal() || r.status == RequestStatus::Error)
+                })
+            })
+            .map(|(pipe_uuid, _)| *pipe_uuid)
+            .collect();
+        resolved.sort();
+
+        for pipe_uuid in resolved {
+            let group = self.groups.remove(&pipe_uuid).unwrap();
+            let mut resolution = GroupResolution {
+                pipe_uuid,
+                callback: group.callback,
+                extension: group.extension,
+                completed: vec![],
+                failed: vec![],
+                cancelled: vec![],
+                error: group.error,
+            };
+            for uuid in group.members {
+                match self.status(uuid) {
+                    Some(RequestStatus::Completed) => resolution.completed.push(uuid),
+                    Some(RequestStatus::Error) => resolution.failed.push(uuid),
+                    _ => resolution.cancelled.push(uuid),
+                }
+            }
+            report.groups.push(resolution);
+        }
+    }
+
+    fn has_scheduled_retry(&self, queue_type: QueueType) -> bool {
+        self.scheduled_retries
+            .iter()
+            .any(|(_, uuid)| self.records[uuid].queue_type == queue_type)
+    }
+
+    fn queue(&self, queue_type: QueueType) -> &VecDeque<u32> {
+        match queue_type {
+            QueueType::Mutable => &self.mutable_queue,
+            QueueType::Immutable => &self.immutable_queue,
+            QueueType::Passive => &self.passive_queue,
+        }
+    }
+
+    fn queue_mut(&mut self, queue_type: QueueType) -> &mut VecDeque<u32> {
+        match queue_type {
+            QueueType::Mutable => &mut self.mutable_queue,
+            QueueType::Immutable => &mut self.immutable_queue,
+            QueueType::Passive => &mut self.passive_queue,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use transport::block_on;
+
+    #[test]
+    fn test_queue_push_and_length() {
+        let mut queue = DispatchQueue::new("/jsonapi/".to_string());
+
+        let mut params = HashMap::new();
+        params.insert("pid".to_string(), serde_json::json!("TEST"));
+
+        let request = ApiRequest {
+            cmd: "appProductGet".to_string(),
+            params,
+            tag: None,
+            uuid: None,
+            priority: Priority::default(),
+        };
+
+        queue.push_validated(QueueType::Mutable, request).unwrap();
+
+        assert_eq!(queue.length(QueueType::Mutable), 1);
+        assert_eq!(queue.length(QueueType::Immutable), 0);
+    }
+
+    fn product_request(pid: &str, callback: Option<&str>) -> ApiRequest {
+        let mut params = HashMap::new();
+        params.insert("pid".to_string(), serde_json::json!(pid));
+
+        ApiRequest {
+            cmd: "appProductGet".to_string(),
+            params,
+            tag: Some(RequestTag {
+                datapointer: format!("appProductGet|{}", pid),
+                callback: callback.map(String::from),
+                extension: None,
+            }),
+            uuid: None,
+            priority: Priority::default(),
+        }
+    }
+
+    #[test]
+    fn test_process_pipelined_response() {
+        let mut queue = DispatchQueue::new("/jsonapi/".to_string());
+        queue.push_request(
+            QueueType::Mutable,
+            product_request("TEST", Some("showProduct")),
+        );
+        queue.push_request(QueueType::Mutable, product_request("BLUE", None));
+
+        let batch = queue.take_batch(QueueType::Mutable);
+        assert_eq!(queue.in_flight_count(), 2);
+
+        // Responses may come back in any order
+        let response = serde_json::json!({
+            "_rcmd": "pipeline",
+            "@rcmds": [
+                { "_uuid": batch[1].uuid, "_rcmd": "appProductGet", "pid": "BLUE", "%attribs": { "db:id": 2 } },
+                { "_uuid": batch[0].uuid, "_rcmd": "appProductGet", "pid": "TEST", "%attribs": { "db:id": 1 } }
+            ]
+        });
+
+        let callbacks = queue.handle_response(response).callbacks;
+
+        assert_eq!(callbacks.len(), 1);
+        assert_eq!(callbacks[0].callback, "showProduct");
+        assert_eq!(callbacks[0].uuid, batch[0].uuid.unwrap());
+        assert_eq!(queue.data("appProductGet|TEST").unwrap()["pid"], "TEST");
+        assert_eq!(queue.data("appProductGet|BLUE").unwrap()["pid"], "BLUE");
+        assert_eq!(queue.in_flight_count(), 0);
+    }
+
+    #[test]
+    fn test_request_status_lifecycle() {
+        let mut queue = DispatchQueue::new("/jsonapi/".to_string());
+        let kept = queue
+            .push_request(QueueType::Mutable, product_request("TEST", None))
+            .unwrap();
+        let aborted = queue
+            .push_request(QueueType::Mutable, product_request("BLUE", None))
+            .unwrap();
+        let cart = queue
+            .push_request(QueueType::Immutable, product_request("CART", None))
+            .unwrap();
+
+        assert_eq!(queue.status(kept), Some(RequestStatus::Queued));
+        assert_eq!(queue.which_queue(cart), Some(QueueType::Immutable));
+
+        assert!(queue.abort_request(aborted));
+        assert!(!queue.abort_request(cart));
+        assert_eq!(queue.status(aborted), Some(RequestStatus::Cancelled));
+        assert_eq!(queue.length(QueueType::Mutable), 1);
+
+        let batch = queue.take_batch(QueueType::Mutable);
+        assert_eq!(batch.len(), 1);
+        assert_eq!(queue.status(kept), Some(RequestStatus::Requesting));
+
+        assert!(queue.fail_request(kept));
+        assert!(queue.retry_request(kept));
+        assert_eq!(queue.status(kept), Some(RequestStatus::Queued));
+        assert_eq!(queue.length(QueueType::Mutable), 1);
+
+        queue.take_batch(QueueType::Mutable);
+        queue.handle_response(serde_json::json!({ "_uuid": kept, "_rcmd": "appProductGet", "%attribs": { "db:id": 1 } }));
+        assert_eq!(queue.status(kept), Some(RequestStatus::Completed));
+
+        assert_eq!(queue.clear_finished(), 2);
+        assert_eq!(queue.status(kept), None);
+    }
+
+    #[test]
+    fn test_push_skips_fresh_datapointer() {
+        let mut queue = DispatchQueue::new("/jsonapi/".to_string());
+        let uuid = queue.push_request(QueueType::Mutable, product_request("TEST", None));
+        queue.take_batch(QueueType::Mutable);
+        queue.handle_response(serde_json::json!({ "_uuid": uuid, "_rcmd": "appProductGet", "%attribs": { "db:id": 1 } }));
+
+        assert!(queue.is_fresh("appProductGet|TEST"));
+        assert_eq!(
+            queue.push_request(QueueType::Mutable, product_request("TEST", None)),
+            None
+        );
+        assert!(queue
+            .push_request(QueueType::Immutable, product_request("TEST", None))
+            .is_some());
+
+        queue.invalidate("appProductGet|TEST");
+        assert!(queue
+            .push_request(QueueType::Mutable, product_request("TEST", None))
+            .is_some());
+    }
+
+    #[test]
+    fn test_error_routing() {
+        let mut queue = DispatchQueue::new("/jsonapi/".to_string());
+        let found = queue
+            .push_request(
+                QueueType::Mutable,
+                product_request("TEST", Some("showProduct")),
+            )
+            .unwrap();
+        let missing = queue
+            .push_request(
+                QueueType::Mutable,
+                product_request("NOPE", Some("showProduct")),
+            )
+            .unwrap();
+        let silent = queue
+            .push_request(QueueType::Mutable, product_request("BLUE", None))
+            .unwrap();
+        queue.take_batch(QueueType::Mutable);
+
+        let report = queue.handle_response(serde_json::json!({
+            "_rcmd": "pipeline",
+            "@rcmds": [
+                { "_uuid": found, "pid": "TEST", "%attribs": { "db:id": 1 } },
+                { "_uuid": missing, "pid": "NOPE", "%attribs": {} },
+                { "_uuid": silent, "errid": 3, "errtype": "apierr", "errmsg": "Denied" }
+            ]
+        }));
+
+        assert!(report.callbacks[0].error.is_none());
+        assert!(matches!(
+            report.callbacks[1].error,
+            Some(ApiError::Missing { .. })
+        ));
+        assert_eq!(report.unhandled[0].uuid, Some(silent));
+        assert_eq!(queue.status(missing), Some(RequestStatus::Error));
+        assert!(queue.data("appProductGet|NOPE").is_none());
+
+        // An ISE on the pipe fails every request in the batch
+        *queue.retry_policy_mut(QueueType::Mutable) = RetryPolicy::none();
+        queue.retry_request(missing);
+        queue.retry_request(silent);
+        let ise = CannedTransport(serde_json::json!({
+            "_rcmd": "err", "errid": 500, "errtype": "iseerr", "errmsg": "boom"
+        }));
+        let report = block_on(queue.dispatch(QueueType::Mutable, &ise));
+        assert!(matches!(
+            report.callbacks[0].error,
+            Some(ApiError::Ise { .. })
+        ));
+        assert_eq!(report.unhandled.len(), 1);
+        assert_eq!(report.unhandled[0].uuid, Some(silent));
+        assert_eq!(queue.status(missing), Some(RequestStatus::Error));
+        assert_eq!(queue.status(silent), Some(RequestStatus::Error));
+
+        // So does a pipelined response that leaves a request out
+        let dropped = queue
+            .push_request(
+                QueueType::Mutable,
+                product_request("GONE", Some("showProduct")),
+            )
+            .unwrap();
+        let empty = CannedTransport(serde_json::json!({ "_rcmd": "pipeline", "@rcmds": [] }));
+        let report = block_on(queue.dispatch(QueueType::Mutable, &empty));
+        assert!(matches!(
+            report.callbacks[0].error,
+            Some(ApiError::Transport { .. })
+        ));
+        assert_eq!(queue.status(dropped), Some(RequestStatus::Error));
+        assert_eq!(queue.in_flight_count(), 0);
+
+        // The outcome of `fetch_batch`, as JS hands it back to `finish_batch`
+        let offline = queue
+            .push_request(QueueType::Mutable, product_request("OFFLINE", None))
+            .unwrap();
+        queue.take_batch(QueueType::Mutable);
+        let outcome: BatchOutcome = serde_json::from_value(serde_json::json!({
+            "uuids": [offline], "error": { "kind": "transport", "message": "offline" }
+        }))
+        .unwrap();
+        let (uuids, result) = outcome.into_parts();
+        let report = queue.complete_batch(&uuids, result);
+        assert_eq!(report.unhandled[0].uuid, Some(offline));
+        assert_eq!(queue.status(offline), Some(RequestStatus::Error));
+
+        // A solo response with only a warning is a success
+        let warned = queue
+            .push_request(QueueType::Mutable, product_request("WARN", None))
+            .unwrap();
+        queue.take_batch(QueueType::Mutable);
+        let report = queue.handle_response(serde_json::json!({
+            "_uuid": warned, "_rcmd": "appProductGet", "pid": "WARN", "%attribs": { "db:id": 3 },
+            "errid": 7, "errtype": "warn", "errmsg": "Low stock"
+        }));
+        assert!(report.unhandled.is_empty());
+        assert_eq!(queue.status(warned), Some(RequestStatus::Completed));
+    }
+
+    /// Answers every batch with the same response
+    struct CannedTransport(serde_json::Value);
+
+    impl Transport for CannedTransport {
+        fn send(
+            &self,
+            _batch: &OutgoingBatch,
+        ) -> impl std::future::Future<Output = Result<serde_json::Value, ApiError>> {
+            std::future::ready(Ok(self.0.clone()))
+        }
+    }
+
+    #[test]
+    fn test_dispatch_with_mock_transport() {
+        let mut queue = DispatchQueue::new("/jsonapi/".to_string());
+        let transport = MockTransport::new().on(
+            "appProductGet",
+            |request| serde_json::json!({ "pid": request["pid"], "%attribs": { "db:id": 1 } }),
+        );
+
+        let uuid = queue
+            .push_request(
+                QueueType::Mutable,
+                product_request("TEST", Some("showProduct")),
+            )
+            .unwrap();
+        let report = block_on(queue.dispatch(QueueType::Mutable, &transport));
+
+        assert_eq!(report.callbacks[0].uuid, uuid);
+        assert_eq!(queue.status(uuid), Some(RequestStatus::Completed));
+        assert_eq!(queue.data("appProductGet|TEST").unwrap()["pid"], "TEST");
+        assert_eq!(transport.sent()[0].body[0]["_uuid"], uuid);
+
+        // Nothing queued, nothing sent
+        block_on(queue.dispatch(QueueType::Mutable, &transport));
+        assert_eq!(transport.sent().len(), 1);
+
+        let failed = queue
+            .push_request(
+                QueueType::Mutable,
+                product_request("BLUE", Some("showProduct")),
+            )
+            .unwrap();
+        *queue.retry_policy_mut(QueueType::Mutable) = RetryPolicy::none();
+        transport.fail_next(ApiError::Transport {
+            message: "offline".to_string(),
+        });
+        let report = block_on(queue.dispatch(QueueType::Mutable, &transport));
+        assert!(matches!(
+            report.callbacks[0].error,
+            Some(ApiError::Transport { .. })
+        ));
+        assert_eq!(queue.status(failed), Some(RequestStatus::Error));
+    }
+
+    #[test]
+    fn test_retry_backoff_and_dead_letters() {
+        let mut queue = DispatchQueue::new("/jsonapi/".to_string());
+        *queue.retry_policy_mut(QueueType::Immutable) = RetryPolicy {
+            max_attempts: 2,
+            base_delay_ms: 0,
+            max_delay_ms: 0,
+            jitter: 0.0,
+        };
+        let transport = MockTransport::new().on(
+            "appProductGet",
+            |_| serde_json::json!({ "%attribs": { "db:id": 1 } }),
+        );
+        let offline = || ApiError::Transport {
+            message: "offline".to_string(),
+        };
+
+        let uuid = queue
+            .push_request(QueueType::Immutable, product_request("TEST", None))
+            .unwrap();
+        transport.fail_next(offline());
+        let report = block_on(queue.dispatch(QueueType::Immutable, &transport));
+        assert_eq!(report.retrying, vec![uuid]);
+        assert!(report.unhandled.is_empty());
+        assert_eq!(queue.status(uuid), Some(RequestStatus::Queued));
+
+        // Second and last attempt
+        transport.fail_next(offline());
+        let report = block_on(queue.dispatch(QueueType::Immutable, &transport));
+        assert!(report.retrying.is_empty());
+        assert_eq!(report.unhandled[0].uuid, Some(uuid));
+        assert_eq!(queue.dead_letters()[0].attempts, 2);
+        assert_eq!(queue.status(uuid), Some(RequestStatus::Error));
+
+        assert!(queue.requeue_dead_letter(uuid));
+        assert!(queue.dead_letters().is_empty());
+        block_on(queue.dispatch(QueueType::Immutable, &transport));
+        assert_eq!(queue.status(uuid), Some(RequestStatus::Completed));
+        // Three sends, one wait in the queue
+        let product = &queue.metrics().commands["appProductGet"];
+        assert_eq!((product.sent, product.retried), (3, 2));
+        assert_eq!(product.queue_wait_ms.count, 1);
+        assert_eq!(product.latency_ms.count, 2);
+
+        // Orders are not idempotent and are never re-sent automatically
+        let order = queue
+            .push_request(
+                QueueType::Immutable,
+                ApiRequest {
+                    cmd: "cartOrderCreate".to_string(),
+                    params: HashMap::new(),
+                    tag: None,
+                    uuid: None,
+                    priority: Priority::default(),
+                },
+            )
+            .unwrap();
+        transport.fail_next(offline());
+        block_on(queue.dispatch(QueueType::Immutable, &transport));
+        assert_eq!(queue.dead_letters()[0].uuid, order);
+    }
+
+    #[test]
+    fn test_persist_and_replay() {
+        let mut storage = MemoryStorage::new();
+        let mut queue = DispatchQueue::new("/jsonapi/".to_string());
+        let sent = queue
+            .push_request(QueueType::Immutable, product_request("SENT", None))
+            .unwrap();
+        queue.take_batch(QueueType::Immutable);
+        let first = queue
+            .push_request(QueueType::Immutable, product_request("FIRST", None))
+            .unwrap();
+        let second = queue
+            .push_request(QueueType::Immutable, product_request("SECOND", None))
+            .unwrap();
+        let browse = queue
+            .push_request(QueueType::Mutable, product_request("BROWSE", None))
+            .unwrap();
+        let queued_at = queue.record(browse).unwrap().queued_at;
+        queue.save_to(&mut storage, queued_at).unwrap();
+
+        // Reload ten minutes later
+        let mut restored = DispatchQueue::new("/jsonapi/".to_string());
+        let report = restored
+            .restore_from(&mut storage, queued_at + 600, 300)
+            .unwrap();
+        assert_eq!(report.dropped, vec![browse]);
+        assert_eq!(report.interrupted.retrying, vec![sent]);
+        assert_eq!(restored.status(first), Some(RequestStatus::Queued));
+        assert_eq!(restored.length(QueueType::Mutable), 0);
+        assert!(storage.get(QUEUE_STORAGE_KEY).is_none());
+
+        // The interrupted request goes first, then the rest in their original order
+        restored.release_retries_at(u64::MAX);
+        let order: Vec<Option<u32>> = (0..3)
+            .map(|_| {
+                let uuid = restored.take_batch(QueueType::Immutable)[0].uuid;
+                restored.fail_requests(
+                    &uuid.into_iter().collect::<Vec<_>>(),
+                    ApiError::Missing {
+                        code: String::new(),
+                        message: String::new(),
+                    },
+                );
+                uuid
+            })
+            .collect();
+        assert_eq!(order, vec![Some(sent), Some(first), Some(second)]);
+
+        // New requests never reuse a restored UUID
+        assert!(restored
+            .push_request(QueueType::Mutable, product_request("NEW", None))
+            .is_some_and(|uuid| uuid > second));
+
+        // Credentials never reach storage
+        let login = commands::AppBuyerLogin::new("buyer@example.com", "hunter2");
+        let login = restored.push_typed(&login, None).unwrap().unwrap();
+        restored.save_to(&mut storage, queued_at).unwrap();
+        let saved = storage.get(QUEUE_STORAGE_KEY).unwrap();
+        assert!(!saved.contains("hunter2"));
+        let snapshot: QueueSnapshot = serde_json::from_str(&saved).unwrap();
+        assert!(!snapshot.records.contains_key(&login));
+        assert!(!snapshot.immutable.contains(&login));
+    }
+
+    #[test]
+    fn test_pipeline_groups() {
+        let mut queue = DispatchQueue::new("/jsonapi/".to_string());
+        let transport = MockTransport::new()
+            .on("cartDetail", |_| serde_json::json!({ "@ITEMS": [] }))
+            .on("appPaymentMethods", |_| serde_json::json!({ "@methods": [] }))
+            .on("appCheckoutDestinations", |_| {
+                serde_json::json!({ "errid": 9, "errtype": "apperr", "errmsg": "No destinations" })
+            });
+        let request = |cmd: &str| ApiRequest {
+            cmd: cmd.to_string(),
+            params: HashMap::new(),
+            tag: None,
+            uuid: None,
+            priority: Priority::default(),
+        };
+
+        let checkout = queue
+            .push_group_requests(
+                QueueType::Immutable,
+                vec![request("cartDetail"), request("appPaymentMethods")],
+                Some("checkoutReady".to_string()),
+                None,
+            )
+            .unwrap();
+        let members = queue.group_uuids(checkout);
+        assert_eq!(queue.pipe_uuid(members[1]), Some(checkout));
+
+        // The whole group is sent at once, even from the immutable queue
+        let report = block_on(queue.dispatch(QueueType::Immutable, &transport));
+        assert_eq!(transport.sent()[0].body.as_array().unwrap().len(), 2);
+        assert_eq!(report.groups.len(), 1);
+        assert_eq!(report.groups[0].callback.as_deref(), Some("checkoutReady"));
+        assert!(report.groups[0].is_success());
+        assert!(queue.group_uuids(checkout).is_empty());
+
+        // A member that fails for good cancels the members not yet sent
+        let failing = queue
+            .push_group_requests(
+                QueueType::Immutable,
+                vec![request("appCheckoutDestinations")],
+                None,
+                None,
+            )
+            .unwrap();
+        let report = block_on(queue.dispatch(QueueType::Immutable, &transport));
+        assert_eq!(report.groups[0].pipe_uuid, failing);
+        assert!(matches!(report.groups[0].error, Some(ApiError::Api { .. })));
+
+        // Aborting one member of a mutable group aborts all of it
+        let browse = queue
+            .push_group_requests(
+                QueueType::Mutable,
+                vec![request("cartDetail"), request("appPaymentMethods")],
+                None,
+                None,
+            )
+            .unwrap();
+        let members = queue.group_uuids(browse);
+        assert!(queue.abort_request(members[0]));
+        assert_eq!(queue.status(members[1]), Some(RequestStatus::Cancelled));
+        assert_eq!(queue.length(QueueType::Mutable), 0);
+    }
+
+    #[test]
+    fn test_coalesce_duplicate_requests() {
+        let mut queue = DispatchQueue::new("/jsonapi/".to_string());
+        let first = queue
+            .push_request(
+                QueueType::Mutable,
+                product_request("TEST", Some("showTile")),
+            )
+            .unwrap();
+        let second = queue
+            .push_request(
+                QueueType::Mutable,
+                product_request("TEST", Some("showDetail")),
+            )
+            .unwrap();
+        queue.push_request(QueueType::Mutable, product_request("BLUE", None));
+        assert_ne!(first, second);
+        assert_eq!(queue.length(QueueType::Mutable), 2);
+
+        let batch = queue.take_batch(QueueType::Mutable);
+        assert_eq!(batch.len(), 2);
+        assert_eq!(queue.status(second), Some(RequestStatus::Requesting));
+
+        let report = queue.handle_response(serde_json::json!({
+            "_uuid": first, "_rcmd": "appProductGet", "pid": "TEST", "%attribs": { "db:id": 1 }
+        }));
+        let callbacks: Vec<&str> = report
+            .callbacks
+            .iter()
+            .map(|c| c.callback.as_str())
+            .collect();
+        assert_eq!(callbacks, vec!["showTile", "showDetail"]);
+        assert_eq!(queue.status(second), Some(RequestStatus::Completed));
+
+        // Aborting the request that would be sent hands its place to the duplicate
+        queue.invalidate("appProductGet|TEST");
+        let tile = queue
+            .push_request(
+                QueueType::Mutable,
+                product_request("TEST", Some("showTile")),
+            )
+            .unwrap();
+        let detail = queue
+            .push_request(
+                QueueType::Mutable,
+                product_request("TEST", Some("showDetail")),
+            )
+            .unwrap();
+        assert!(queue.abort_request(tile));
+        let batch = queue.take_batch(QueueType::Mutable);
+        assert_eq!(batch.len(), 1);
+        assert_eq!(batch[0].uuid, Some(detail));
+    }
+
+    #[test]
+    fn test_push_typed_command() {
+        let mut queue = DispatchQueue::new("/jsonapi/".to_string());
+        let transport = MockTransport::new().on(
+            "appProductGet",
+            |request| serde_json::json!({ "pid": request["pid"], "%attribs": { "db:id": 1 } }),
+        );
+
+        let command = commands::AppProductGet::new("test");
+        assert!(queue.push_typed(&command, None).unwrap().is_some());
+        assert!(queue
+            .push_typed(&commands::AppProductGet::new(""), None)
+            .is_err());
+
+        block_on(queue.dispatch(QueueType::Mutable, &transport));
+        let product = queue.typed_data(&command).unwrap().unwrap();
+        assert_eq!(product.pid, "TEST");
+
+        // Legacy payloads pass validation and are queued as sent: qty from
+        // `serializeJSON` as a string, `%variations`, and the admin cartItemUpdate shape
+        let append = serde_json::json!({
+            "_cartid": "CART1", "sku": "TEST:0209", "qty": "2",
+            "%variations": { "02": "09", "A1": "ON" }
+        });
+        let uuid = queue
+            .push_params("cartItemAppend", append.clone(), None, None)
+            .unwrap()
+            .unwrap();
+        let params = &queue.record(uuid).unwrap().request.params;
+        assert_eq!(params["qty"], "2");
+        assert_eq!(params["%variations"], append["%variations"]);
+
+        let update = serde_json::json!({
+            "_cmd": "cartItemUpdate", "_cartid": "CART1", "uuid": "@1", "qty": "0"
+        });
+        let request: ApiRequest = serde_json::from_value(update).unwrap();
+        assert!(queue
+            .push_validated(QueueType::Immutable, request)
+            .unwrap()
+            .is_some());
+        let missing_item: ApiRequest = serde_json::from_value(serde_json::json!({
+            "_cmd": "cartItemUpdate", "_cartid": "CART1", "quantity": 1
+        }))
+        .unwrap();
+        assert!(queue
+            .push_validated(QueueType::Immutable, missing_item)
+            .is_err());
+        assert_eq!(queue.length(QueueType::Immutable), 2);
+    }
+
+    #[test]
+    fn test_batch_limits_and_visible_first() {
+        let mut queue = DispatchQueue::new("/jsonapi/".to_string());
+        *queue.batch_limits_mut() = BatchLimits {
+            max_requests: 2,
+            max_bytes: usize::MAX,
+        };
+        queue.set_visible_products(vec!["c".to_string(), "d".to_string()]);
+
+        let uuids: Vec<u32> = ["A", "B", "C", "D", "E"]
+            .iter()
+            .map(|pid| {
+                queue
+                    .push_request(QueueType::Mutable, product_request(pid, None))
+                    .unwrap()
+            })
+            .collect();
+
+        let batch = queue.take_batch(QueueType::Mutable);
+        let sent: Vec<u32> = batch.iter().filter_map(|r| r.uuid).collect();
+        assert_eq!(sent, vec![uuids[2], uuids[3]]);
+        assert_eq!(queue.length(QueueType::Mutable), 3);
+        assert_eq!(queue.status(uuids[0]), Some(RequestStatus::Queued));
+
+        let batch = queue.take_batch(QueueType::Mutable);
+        let sent: Vec<u32> = batch.iter().filter_map(|r| r.uuid).collect();
+        assert_eq!(sent, vec![uuids[0], uuids[1]]);
+
+        // A request over the byte limit still goes out, alone
+        queue.batch_limits_mut().max_bytes = 1;
+        queue.push_request(QueueType::Mutable, product_request("F", None));
+        assert_eq!(queue.take_batch(QueueType::Mutable).len(), 1);
+        assert_eq!(queue.length(QueueType::Mutable), 1);
+    }
+
+    #[test]
+    fn test_mutable_queue_priorities() {
+        let mut queue = DispatchQueue::new("/jsonapi/".to_string());
+        let push = |queue: &mut DispatchQueue, pid: &str, priority: Priority| {
+            let request = ApiRequest {
+                priority,
+                ..product_request(pid, None)
+            };
+            queue.push_request(QueueType::Mutable, request).unwrap()
+        };
+        let push_passive = |queue: &mut DispatchQueue, pid: &str, priority: Priority| {
+            let request = ApiRequest {
+                priority,
+                ..product_request(pid, None)
+            };
+            queue.push_request(QueueType::Passive, request).unwrap()
+        };
+
+        let low = push(&mut queue, "A", Priority::Low);
+        let normal = push(&mut queue, "B", Priority::Normal);
+        let high = push(&mut queue, "C", Priority::High);
+        let normal2 = push(&mut queue, "D", Priority::Normal);
+        let high2 = push(&mut queue, "E", Priority::High);
+        // A high priority duplicate moves the queued request up
+        let duplicate = push(&mut queue, "A", Priority::High);
+
+        let batch = queue.take_batch(QueueType::Mutable);
+        let sent: Vec<u32> = batch.iter().filter_map(|r| r.uuid).collect();
+        assert_eq!(sent, vec![high, high2, low, normal, normal2]);
+        assert_eq!(queue.status(duplicate), Some(RequestStatus::Requesting));
+        assert!(batch.iter().all(|r| r.priority == Priority::Normal));
+        assert!(!serde_json::to_string(&batch).unwrap().contains("_priority"));
+
+        let later = push(&mut queue, "F", Priority::Low);
+        let prefetch = push(&mut queue, "G", Priority::Low);
+        assert!(queue.set_priority(prefetch, Priority::High));
+        let sent: Vec<u32> = queue
+            .take_batch(QueueType::Mutable)
+            .iter()
+            .filter_map(|r| r.uuid)
+            .collect();
+        assert_eq!(sent, vec![prefetch, later]);
+
+        // FIFO queues keep their order, whatever the priority
+        let first = queue
+            .push_request(QueueType::Immutable, cart_request("A"))
+            .unwrap();
+        let second = queue
+            .push_request(QueueType::Immutable, cart_request("B"))
+            .unwrap();
+        assert!(queue.set_priority(first, Priority::Low));
+        assert!(queue.set_priority(second, Priority::High));
+        assert_eq!(queue.queue(QueueType::Immutable), &[first, second]);
+
+        let first = push_passive(&mut queue, "A", Priority::Low);
+        let second = push_passive(&mut queue, "B", Priority::Normal);
+        push_passive(&mut queue, "A", Priority::High);
+        assert_eq!(queue.queue(QueueType::Passive), &[first, second]);
+        queue.take_batch(QueueType::Passive);
+        assert!(!queue.set_priority(first, Priority::Low));
+        assert_eq!(
+            queue.record(first).unwrap().request.priority,
+            Priority::High
+        );
+    }
+
+    fn cart_request(stid: &str) -> ApiRequest {
+        let mut params = HashMap::new();
+        params.insert("_cartid".to_string(), serde_json::json!("CART1"));
+        params.insert("stid".to_string(), serde_json::json!(stid));
+        params.insert("quantity".to_string(), serde_json::json!(1));
+
+        ApiRequest {
+            cmd: "cartItemUpdate".to_string(),
+            params,
+            tag: None,
+            uuid: None,
+            priority: Priority::default(),
+        }
+    }
+
+    fn ack(uuid: u32) -> serde_json::Value {
+        serde_json::json!({
+            "_rcmd": "pipeline",
+            "@rcmds": [{ "_uuid": uuid, "_rcmd": "cartItemUpdate" }]
+        })
+    }
+
+    #[test]
+    fn test_immutable_lock_released_only_by_ack() {
+        let mut queue = DispatchQueue::new("/jsonapi/".to_string());
+        let first = queue
+            .push_request(QueueType::Immutable, cart_request("A"))
+            .unwrap();
+        let second = queue
+            .push_request(QueueType::Immutable, cart_request("B"))
+            .unwrap();
+
+        assert_eq!(queue.take_batch(QueueType::Immutable).len(), 1);
+        assert!(queue.is_immutable_locked());
+        assert!(queue.take_batch(QueueType::Immutable).is_empty());
+        assert!(!queue.abort_request(first));
+
+        // Unrelated responses and acks for other queues leave the lock alone
+        queue.handle_response(ack(first + 100));
+        queue.push_request(QueueType::Mutable, product_request("TEST", None));
+        queue.take_batch(QueueType::Mutable);
+        assert!(queue.take_batch(QueueType::Immutable).is_empty());
+
+        queue.handle_response(ack(first));
+        assert!(!queue.is_immutable_locked());
+        let batch = queue.take_batch(QueueType::Immutable);
+        assert_eq!(batch[0].uuid, Some(second));
+
+        // A failure ack releases the lock too
+        let third = queue
+            .push_request(QueueType::Immutable, cart_request("C"))
+            .unwrap();
+        assert!(queue.take_batch(QueueType::Immutable).is_empty());
+        queue.fail_requests(
+            &[second],
+            ApiError::Missing {
+                code: "MVC-M-100".to_string(),
+                message: "Item not in cart".to_string(),
+            },
+        );
+        assert_eq!(queue.take_batch(QueueType::Immutable)[0].uuid, Some(third));
+    }
+
+    #[test]
+    fn test_immutable_lock_released_by_pipe_error() {
+        let mut queue = DispatchQueue::new("/jsonapi/".to_string());
+        *queue.retry_policy_mut(QueueType::Immutable) = RetryPolicy::none();
+        let first = queue
+            .push_request(QueueType::Immutable, cart_request("A"))
+            .unwrap();
+        let second = queue
+            .push_request(QueueType::Immutable, cart_request("B"))
+            .unwrap();
+
+        let ise = CannedTransport(serde_json::json!({
+            "_rcmd": "err", "errid": 500, "errtype": "iseerr", "errmsg": "boom"
+        }));
+        block_on(queue.dispatch(QueueType::Immutable, &ise));
+        assert_eq!(queue.status(first), Some(RequestStatus::Error));
+        assert!(!queue.is_immutable_locked());
+
+        // A response that drops the request releases it too
+        let empty = CannedTransport(serde_json::json!({ "_rcmd": "pipeline", "@rcmds": [] }));
+        block_on(queue.dispatch(QueueType::Immutable, &empty));
+        assert_eq!(queue.status(second), Some(RequestStatus::Error));
+        assert!(!queue.is_immutable_locked());
+    }
+
+    #[test]
+    fn test_cart_mutations_never_interleave() {
+        let mut queue = DispatchQueue::new("/jsonapi/".to_string());
+        *queue.retry_policy_mut(QueueType::Immutable) = RetryPolicy {
+            max_attempts: 3,
+            base_delay_ms: 0,
+            max_delay_ms: 0,
+            jitter: 0.0,
+        };
+        let mut rng = Xorshift::new(7);
+        let mut pushed = vec![];
+        let mut finished = vec![];
+        let mut in_flight: Vec<u32> = vec![];
+
+        for step in 0..2000 {
+            match rng.next_u64() % 4 {
+                0 if pushed.len() < 200 => {
+                    let request = cart_request(&format!("SKU{}", step));
+                    pushed.push(queue.push_request(QueueType::Immutable, request).unwrap());
+                }
+                1 => {
+                    let batch = queue.take_batch(QueueType::Immutable);
+                    if !in_flight.is_empty() {
+                        assert!(batch.is_empty(), "sent while {:?} in flight", in_flight);
+                    }
+                    in_flight.extend(batch.iter().filter_map(|r| r.uuid));
+                    assert!(in_flight.len() <= 1);
+                    assert_eq!(queue.immutable_in_flight(), in_flight);
+                }
+                2 if !in_flight.is_empty() => {
+                    let uuid = in_flight.remove(0);
+                    queue.handle_response(ack(uuid));
+                    finished.push(uuid);
+                }
+                3 if !in_flight.is_empty() => {
+                    let uuid = in_flight.remove(0);
+                    queue.fail_requests(
+                        &[uuid],
+                        ApiError::Ise {
+                            code: "ISE".to_string(),
+                            message: "try again".to_string(),
+                        },
+                    );
+                    if queue.status(uuid) == Some(RequestStatus::Error) {
+                        finished.push(uuid);
+                    }
+                }
+                _ => {}
+            }
+        }
+
+        // Every request finished in the order it was pushed, retries included
+        assert!(!finished.is_empty());
+        assert_eq!(finished, pushed[..finished.len()]);
+    }
+
+    #[test]
+    fn test_session_headers_and_logins() {
+        let mut queue = DispatchQueue::new("/jsonapi/".to_string());
+        queue.set_session_id("S1".to_string());
+        let transport = MockTransport::new()
+            .on("authAdminLogin", |_| {
+                serde_json::json!({
+                    "authtoken": "T1", "userid": "ACME", "username": "bob", "deviceid": "D1"
+                })
+            })
+            .on("appBuyerLogin", |_| serde_json::json!({ "cid": 7 }))
+            .on("buyerLogout", |_| serde_json::json!({}));
+        let request = |cmd: &str, params: serde_json::Value| ApiRequest {
+            cmd: cmd.to_string(),
+            params: serde_json::from_value(params).unwrap(),
+            tag: None,
+            uuid: None,
+            priority: Priority::default(),
+        };
+        let header = |batch: &OutgoingBatch, name: &str| {
+            batch
+                .headers
+                .iter()
+                .find(|(n, _)| n == name)
+                .map(|(_, value)| value.clone())
+        };
+
+        queue.push_request(
+            QueueType::Immutable,
+            request("authAdminLogin", serde_json::json!({ "password": "x" })),
+        );
+        block_on(queue.dispatch(QueueType::Immutable, &transport));
+        assert!(queue.is_admin_session());
+        assert_eq!(
+            header(&transport.sent()[0], "x-session").as_deref(),
+            Some("S1")
+        );
+        assert_eq!(header(&transport.sent()[0], "x-authtoken"), None);
+
+        queue.push_request(
+            QueueType::Immutable,
+            request("appBuyerLogin", serde_json::json!({ "login": "a@b.com" })),
+        );
+        block_on(queue.dispatch(QueueType::Immutable, &transport));
+        assert_eq!(
+            header(&transport.sent()[1], "x-authtoken").as_deref(),
+            Some("T1")
+        );
+        assert_eq!(queue.session().buyer.as_ref().unwrap().login, "a@b.com");
+
+        // errid 10 on the pipe means the admin token expired
+        queue.push_request(QueueType::Mutable, product_request("TEST", None));
+        queue.take_batch(QueueType::Mutable);
+        let report = queue.handle_response(serde_json::json!({
+            "_rcmd": "err", "errid": 10, "errtype": "apierr", "errmsg": "token expired"
+        }));
+        assert!(report.session_expired);
+        assert!(!queue.is_admin_session());
+        assert!(queue.is_buyer_logged_in());
+
+        queue.push_request(
+            QueueType::Immutable,
+            request("buyerLogout", serde_json::json!({})),
+        );
+        block_on(queue.dispatch(QueueType::Immutable, &transport));
+        assert!(!queue.is_buyer_logged_in());
+        assert_eq!(queue.session().session_id.as_deref(), Some("S1"));
+    }
+
+    #[test]
+    fn test_dispatch_events() {
+        use DispatchEventKind::*;
+
+        let mut queue = DispatchQueue::new("/jsonapi/".to_string());
+        let transport =
+            MockTransport::new().on("appProductGet", |request| match request["pid"].as_str() {
+                Some("TEST") => serde_json::json!({ "pid": "TEST", "%attribs": { "db:id": 1 } }),
+                _ => serde_json::json!({}),
+            });
+        let events = std::rc::Rc::new(std::cell::RefCell::new(vec![]));
+        let seen = events.clone();
+        queue.subscribe(None, move |e| seen.borrow_mut().push((e.kind, e.uuid)));
+        let retried = events.clone();
+        queue.subscribe(Some(Retried), move |e| {
+            assert_eq!(e.datapointer.as_deref(), Some("appProductGet|RETRY"));
+            retried.borrow_mut().push((Retried, 0));
+        });
+
+        let push = |queue: &mut DispatchQueue, pid: &str| {
+            queue
+                .push_request(QueueType::Mutable, product_request(pid, None))
+                .unwrap()
+        };
+        let found = push(&mut queue, "TEST");
+        let missing = push(&mut queue, "GONE");
+        let aborted = push(&mut queue, "SKIP");
+        queue.abort_request(aborted);
+        block_on(queue.dispatch(QueueType::Mutable, &transport));
+
+        let retry = push(&mut queue, "RETRY");
+        let duplicate = push(&mut queue, "RETRY");
+        transport.fail_next(ApiError::Transport {
+            message: "offline".to_string(),
+        });
+        block_on(queue.dispatch(QueueType::Mutable, &transport));
+
+        assert_eq!(
+            *events.borrow(),
+            vec![
+                (Enqueued, found),
+                (Enqueued, missing),
+                (Enqueued, aborted),
+                (Aborted, aborted),
+                (Sent, found),
+                (Sent, missing),
+                (Completed, found),
+                (Failed, missing),
+                (Enqueued, retry),
+                (Enqueued, duplicate),
+                (Sent, retry),
+                (Sent, duplicate),
+                (Retried, retry),
+                (Retried, 0),
+                (Retried, duplicate),
+                (Retried, 0),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_dispatch_metrics() {
+        let mut queue = DispatchQueue::new("/jsonapi/".to_string());
+        let transport =
+            MockTransport::new().on("appProductGet", |request| match request["pid"].as_str() {
+                Some("TEST") => serde_json::json!({ "pid": "TEST", "%attribs": { "db:id": 1 } }),
+                _ => serde_json::json!({}),
+            });
+
+        queue.push_request(QueueType::Mutable, product_request("TEST", None));
+        queue.push_request(QueueType::Mutable, product_request("GONE", None));
+        block_on(queue.dispatch(QueueType::Mutable, &transport));
+        queue.push_request(QueueType::Mutable, product_request("RETRY", None));
+        transport.fail_next(ApiError::Transport {
+            message: "offline".to_string(),
+        });
+        block_on(queue.dispatch(QueueType::Mutable, &transport));
+
+        let product = &queue.metrics().commands["appProductGet"];
+        assert_eq!(product.sent, 3);
+        assert_eq!(product.completed, 1);
+        assert_eq!(product.failed, 1);
+        assert_eq!(product.retried, 1);
+        assert_eq!(product.error_rate(), 0.5);
+        assert_eq!(product.latency_ms.count, 2);
+        assert_eq!(product.queue_wait_ms.count, 3);
+        let batches = &queue.metrics().batches[&QueueType::Mutable];
+        assert_eq!(batches.requests.sum, 3.0);
+        assert!(queue
+            .metrics_text()
+            .contains("anycommerce_dispatch_batch_requests_count{queue=\"mutable\"} 2\n"));
+
+        queue.reset_metrics();
+        assert!(queue.metrics().commands.is_empty());
+    }
+
+    #[test]
+    fn test_record_then_replay_offline() {
+        let transport =
+            MockTransport::new().on("appProductGet", |request| match request["pid"].as_str() {
+                Some("TEST") => serde_json::json!({ "pid": "TEST", "%attribs": { "db:id": 1 } }),
+                _ => serde_json::json!({}),
+            });
+        let push = |queue: &mut DispatchQueue| {
+            queue.push_request(QueueType::Mutable, product_request("TEST", Some("show")));
+            queue.push_request(QueueType::Mutable, product_request("GONE", Some("show")));
+        };
+
+        let mut live = DispatchQueue::new("/jsonapi/".to_string());
+        live.start_recording(0);
+        push(&mut live);
+        block_on(live.dispatch(QueueType::Mutable, &transport));
+        let jsonl = live.stop_recording().unwrap();
+        assert_eq!(jsonl.lines().count(), 2);
+        assert!(live.recording().is_none());
+
+        // Aborted requests are forgotten, not left waiting for a response
+        live.start_recording(0);
+        let aborted = live
+            .push_request(QueueType::Mutable, product_request("ABORT", None))
+            .unwrap();
+        live.take_batch(QueueType::Mutable);
+        assert_eq!(live.recorder().unwrap().in_flight(), 1);
+        live.abort_request(aborted);
+        assert_eq!(live.recorder().unwrap().in_flight(), 0);
+
+        // A fresh queue sees the same data and the same errors without the API
+        let replay = ReplayTransport::from_jsonl(&jsonl).unwrap();
+        let mut offline = DispatchQueue::new("/jsonapi/".to_string());
+        push(&mut offline);
+        let report = block_on(offline.dispatch(QueueType::Mutable, &replay));
+        assert_eq!(replay.remaining(), 0);
+        assert_eq!(offline.data("appProductGet|TEST").unwrap()["pid"], "TEST");
+        assert!(report.callbacks[0].error.is_none());
+        assert!(matches!(
+            report.callbacks[1].error,
+            Some(ApiError::Missing { .. })
+        ));
     }
 }
@@ -0,0 +1,243 @@
+use serde::{Deserialize, Serialize};
+use std::collections::{HashMap, VecDeque};
+use thiserror::Error;
+
+use super::error::ApiError;
+use super::group::PipelineGroup;
+use super::redact;
+use super::response::ResponseReport;
+use super::retry::DeadLetter;
+use super::status::RequestStatus;
+use super::storage::StorageBackend;
+use super::{DispatchQueue, DispatchRecord, QueueType};
+
+/// Storage key the queue is saved under
+pub const QUEUE_STORAGE_KEY: &str = "dispatchQueue";
+
+/// Bumped whenever the snapshot layout changes. Older snapshots are discarded.
+const SNAPSHOT_VERSION: u32 = 1;
+
+/// Why a queue could not be saved or restored
+#[derive(Debug, Clone, PartialEq, Eq, Error)]
+pub enum PersistError {
+    #[error("Unsupported queue snapshot version {0}")]
+    UnsupportedVersion(u32),
+    #[error("Cannot restore into a queue that already has requests")]
+    QueueNotEmpty,
+    #[error("Failed to serialize queue: {0}")]
+    Serialize(String),
+    #[error("Failed to parse saved queue: {0}")]
+    Parse(String),
+}
+
+impl From<PersistError> for wasm_bindgen::JsValue {
+    fn from(error: PersistError) -> Self {
+        wasm_bindgen::JsValue::from_str(&error.to_string())
+    }
+}
+
+/// Everything needed to rebuild a queue after a reload. Finished requests are not
+/// kept, nor are requests carrying credentials or payment details (logins,
+/// `cartOrderCreate` with `@PAYMENTS`): storage is readable by any script on the
+/// page, so those are lost on reload.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct QueueSnapshot {
+    pub version: u32,
+    pub saved_at: u64,
+    pub next_uuid: u32,
+    pub mutable: Vec<u32>,
+    pub immutable: Vec<u32>,
+    pub passive: Vec<u32>,
+    pub records: HashMap<u32, DispatchRecord>,
+    pub scheduled_retries: Vec<(u64, u32)>,
+    pub dead_letters: Vec<DeadLetter>,
+    #[serde(default)]
+    pub groups: HashMap<u32, PipelineGroup>,
+}
+
+/// What happened to the saved requests when a snapshot was restored
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct RestoreReport {
+    /// Requests put back in their queues
+    pub restored: usize,
+    /// Stale mutable requests and unanswered passive requests that were discarded
+    pub dropped: Vec<u32>,
+    /// Requests that were in flight when the page went away. Retried or failed
+    /// per their queue's retry policy.
+    pub interrupted: ResponseReport,
+}
+
+impl DispatchQueue {
+    /// Capture the queues and every unfinished request that is safe to store
+    pub fn snapshot(&self, now: u64) -> QueueSnapshot {
+        let records: HashMap<u32, DispatchRecord> = self
+            .records
+            .iter()
+            .filter(|(_, r)| !r.status.is_terminal() && !redact::has_sensitive(&r.request.params))
+            .map(|(uuid, r)| (*uuid, r.clone()))
+            .collect();
+        let kept = |queue: &VecDeque<u32>| -> Vec<u32> {
+            queue
+                .iter()
+                .copied()
+                .filter(|uuid| records.contains_key(uuid))
+                .collect()
+        };
+
+        QueueSnapshot {
+            version: SNAPSHOT_VERSION,
+            saved_at: now,
+            next_uuid: self.next_uuid,
+            mutable: kept(&self.mutable_queue),
+            immutable: kept(&self.immutable_queue),
+            passive: kept(&self.passive_queue),
+            scheduled_retries: self
+                .scheduled_retries
+                .iter()
+                .copied()
+                .filter(|(_, uuid)| records.contains_key(uuid))
+                .collect(),
+            records,
+            dead_letters: self.dead_letters.clone(),
+            groups: self.groups.clone(),
+        }
+    }
+
+    /// Rebuild the queues from a snapshot. Only an empty queue can be restored into.
+    /// Immutable requests replay in their original order. Mutable requests queued more
+    /// than `max_mutable_age` seconds before `now` are dropped, as are mutable and
+    /// passive requests that were in flight. In-flight immutable requests are failed
+    /// with a transport error so that their queue's retry policy decides what happens.
+    pub fn restore_snapshot(
+        &mut self,
+        snapshot: QueueSnapshot,
+        now: u64,
+        max_mutable_age: u64,
+    ) -> Result<RestoreReport, PersistError> {
+        if snapshot.version != SNAPSHOT_VERSION {
+            return Err(PersistError::UnsupportedVersion(snapshot.version));
+        }
+        if !self.records.is_empty() {
+            return Err(PersistError::QueueNotEmpty);
+        }
+
+        let mut report = RestoreReport::default();
+        let mut interrupted = vec![];
+
+        for (uuid, record) in snapshot.records {
+            let stale = now.saturating_sub(record.queued_at) > max_mutable_age;
+            let keep = match (record.queue_type, record.status) {
+                (QueueType::Immutable, RequestStatus::Requesting) => {
+                    interrupted.push(uuid);
+                    true
+                }
+                (_, RequestStatus::Requesting) => false,
+                (QueueType::Mutable, RequestStatus::Queued) => !stale,
+                _ => true,
+            };
+
+            if keep {
+                self.records.insert(uuid, record);
+            } else {
+                report.dropped.push(uuid);
+            }
+        }
+
+        // Requests coalesced into a dropped request would never be sent
+        let orphans: Vec<u32> = self
+            .records
+            .iter()
+            .filter(|(_, r)| {
+                r.coalesced_into
+                    .is_some_and(|primary| !self.records.contains_key(&primary))
+            })
+            .map(|(uuid, _)| *uuid)
+            .collect();
+        for uuid in orphans {
+            self.records.remove(&uuid);
+            report.dropped.push(uuid);
+        }
+
+        let keep = |uuids: Vec<u32>, records: &HashMap<u32, DispatchRecord>| -> VecDeque<u32> {
+            uuids
+                .into_iter()
+                .filter(|uuid| records.contains_key(uuid))
+                .collect()
+        };
+        self.mutable_queue = keep(snapshot.mutable, &self.records);
+        self.immutable_queue = keep(snapshot.immutable, &self.records);
+        self.passive_queue = keep(snapshot.passive, &self.records);
+        self.scheduled_retries = snapshot
+            .scheduled_retries
+            .into_iter()
+            .filter(|(_, uuid)| self.records.contains_key(uuid))
+            .collect();
+        self.dead_letters = snapshot.dead_letters;
+        self.groups = snapshot
+            .groups
+            .into_iter()
+            .filter(|(_, group)| {
+                group
+                    .members
+                    .iter()
+                    .any(|uuid| self.records.contains_key(uuid))
+            })
+            .collect();
+        self.next_uuid = self.next_uuid.max(snapshot.next_uuid);
+
+        report.restored = self.mutable_queue.len()
+            + self.immutable_queue.len()
+            + self.passive_queue.len()
+            + self.scheduled_retries.len();
+        report.dropped.sort();
+        interrupted.sort();
+        report.interrupted = self.fail_requests(
+            &interrupted,
+            ApiError::Transport {
+                message: "The page was closed before a response was received".to_string(),
+            },
+        );
+
+        Ok(report)
+    }
+
+    /// Save the queue to a storage backend under `QUEUE_STORAGE_KEY`
+    pub fn save_to(&self, storage: &mut dyn StorageBackend, now: u64) -> Result<(), PersistError> {
+        let json = serde_json::to_string(&self.snapshot(now))
+            .map_err(|e| PersistError::Serialize(e.to_string()))?;
+        storage.set(QUEUE_STORAGE_KEY, json);
+
+        Ok(())
+    }
+
+    /// Restore the queue saved by `save_to`, if there is one. The saved copy is
+    /// removed once it has been restored.
+    pub fn restore_from(
+        &mut self,
+        storage: &mut dyn StorageBackend,
+        now: u64,
+        max_mutable_age: u64,
+    ) -> Result<RestoreReport, PersistError> {
+        let Some(json) = storage.get(QUEUE_STORAGE_KEY) else {
+            return Ok(RestoreReport::default());
+        };
+
+        // A copy that can never be restored is discarded rather than retried on every load
+        let snapshot = match serde_json::from_str::<QueueSnapshot>(&json) {
+            Ok(snapshot) if snapshot.version == SNAPSHOT_VERSION => snapshot,
+            Ok(snapshot) => {
+                storage.remove(QUEUE_STORAGE_KEY);
+                return Err(PersistError::UnsupportedVersion(snapshot.version));
+            }
+            Err(e) => {
+                storage.remove(QUEUE_STORAGE_KEY);
+                return Err(PersistError::Parse(e.to_string()));
+            }
+        };
+
+        let report = self.restore_snapshot(snapshot, now, max_mutable_age)?;
+        storage.remove(QUEUE_STORAGE_KEY);
+
+        Ok(report)
+    }
+}
@@ -0,0 +1,311 @@
+use serde::{Deserialize, Serialize};
+use serde_json::{json, Value};
+use std::cell::RefCell;
+use std::collections::{HashMap, VecDeque};
+use std::future::Future;
+
+use super::error::ApiError;
+use super::redact;
+use super::transport::{OutgoingBatch, Transport};
+use super::{ApiRequest, QueueType};
+
+/// One request as it was sent and what came back for it. A line of the JSONL log.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct RecordedExchange {
+    pub seq: u64,
+    /// When the request was sent, in ms since the epoch
+    pub sent_at: u64,
+    pub queue_type: QueueType,
+    pub request: ApiRequest,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub response: Option<Value>,
+    /// Set instead of `response` when the request failed without one
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub error: Option<ApiError>,
+}
+
+/// Captures every dispatch and its outcome (successor to the entomologist
+/// extension's `response_<uuid>` copies). Only the newest `max_entries` are kept.
+/// Passwords, tokens and payment details are redacted from requests and responses,
+/// since recordings are meant to leave the device.
+#[derive(Debug, Clone)]
+pub struct Recorder {
+    max_entries: usize,
+    next_seq: u64,
+    in_flight: HashMap<u32, RecordedExchange>,
+    entries: VecDeque<RecordedExchange>,
+}
+
+impl Recorder {
+    pub fn new(max_entries: usize) -> Recorder {
+        Recorder {
+            max_entries: max_entries.max(1),
+            next_seq: 1,
+            in_flight: HashMap::new(),
+            entries: VecDeque::new(),
+        }
+    }
+
+    /// A request was sent. It is logged once its response or error arrives.
+    pub fn sent(&mut self, queue_type: QueueType, request: &ApiRequest, now_ms: u64) {
+        let Some(uuid) = request.uuid else {
+            return;
+        };
+        self.in_flight.insert(
+            uuid,
+            RecordedExchange {
+                seq: 0,
+                sent_at: now_ms,
+                queue_type,
+                request: ApiRequest {
+                    params: redact::redact_params(&request.params),
+                    ..request.clone()
+                },
+                response: None,
+                error: None,
+            },
+        );
+    }
+
+    pub fn answered(&mut self, uuid: u32, response: &Value) {
+        if let Some(mut exchange) = self.in_flight.remove(&uuid) {
+            let mut response = response.clone();
+            redact::redact(&mut response);
+            exchange.response = Some(response);
+            self.log(exchange);
+        }
+    }
+
+    pub fn failed(&mut self, uuid: u32, error: &ApiError) {
+        if let Some(mut exchange) = self.in_flight.remove(&uuid) {
+            exchange.error = Some(error.clone());
+            self.log(exchange);
+        }
+    }
+
+    /// A request was cancelled or aborted. Whatever comes back for it is not logged.
+    pub fn cancelled(&mut self, uuid: u32) {
+        self.in_flight.remove(&uuid);
+    }
+
+    /// Requests sent and not yet answered
+    pub fn in_flight(&self) -> usize {
+        self.in_flight.len()
+    }
+
+    pub fn entries(&self) -> impl Iterator<Item = &RecordedExchange> {
+        self.entries.iter()
+    }
+
+    /// The log, one exchange per line, oldest first
+    pub fn to_jsonl(&self) -> String {
+        self.entries
+            .iter()
+            .filter_map(|exchange| serde_json::to_string(exchange).ok())
+            .map(|line| line + "\n")
+            .collect()
+    }
+
+    fn log(&mut self, mut exchange: RecordedExchange) {
+        exchange.seq = self.next_seq;
+        self.next_seq += 1;
+        self.entries.push_back(exchange);
+        while self.entries.len() > self.max_entries {
+            self.entries.pop_front();
+        }
+    }
+}
+
+/// Read a log written by `Recorder::to_jsonl`. Blank lines are skipped.
+pub fn parse_recording(jsonl: &str) -> Result<Vec<RecordedExchange>, String> {
+    jsonl
+        .lines()
+        .enumerate()
+        .filter(|(_, line)| !line.trim().is_empty())
+        .map(|(i, line)| {
+            serde_json::from_str(line)
+                .map_err(|e| format!("Failed to parse recording line {}: {}", i + 1, e))
+        })
+        .collect()
+}
+
+/// Answers requests with recorded responses instead of calling the API. Each
+/// request gets the oldest unused exchange with the same command and params,
+/// compared after redaction.
+/// Requests with no recording get an API error, as an unknown command would.
+pub struct ReplayTransport {
+    exchanges: Vec<RecordedExchange>,
+    used: RefCell<Vec<bool>>,
+}
+
+impl ReplayTransport {
+    pub fn new(exchanges: Vec<RecordedExchange>) -> ReplayTransport {
+        let used = RefCell::new(vec![false; exchanges.len()]);
+        ReplayTransport { exchanges, used }
+    }
+
+    pub fn from_jsonl(jsonl: &str) -> Result<ReplayTransport, String> {
+        parse_recording(jsonl).map(ReplayTransport::new)
+    }
+
+    /// Recorded exchanges not yet replayed
+    pub fn remaining(&self) -> usize {
+        self.used.borrow().iter().filter(|used| !**used).count()
+    }
+
+    fn take(&self, request: &ApiRequest) -> Option<&RecordedExchange> {
+        let params = redact::redact_params(&request.params);
+        let mut used = self.used.borrow_mut();
+        let index = self
+            .exchanges
+            .iter()
+            .enumerate()
+            .position(|(i, exchange)| {
+                !used[i] && exchange.request.cmd == request.cmd && exchange.request.params == params
+            })?;
+        used[index] = true;
+
+        Some(&self.exchanges[index])
+    }
+}
+
+impl Transport for ReplayTransport {
+    fn send(&self, batch: &OutgoingBatch) -> impl Future<Output = Result<Value, ApiError>> {
+        let requests: Vec<ApiRequest> =
+            serde_json::from_value(batch.body.clone()).unwrap_or_default();
+
+        let mut responses = vec![];
+        let mut failure = None;
+        for request in &requests {
+            let response = match self.take(request) {
+                Some(RecordedExchange {
+                    response: Some(response),
+                    ..
+                }) => response.clone(),
+                // The whole batch failed when it was recorded
+                Some(RecordedExchange {
+                    error: Some(error), ..
+                }) if matches!(error, ApiError::Transport { .. }) => {
+                    failure.get_or_insert_with(|| error.clone());
+                    continue;
+                }
+                Some(RecordedExchange {
+                    error: Some(error), ..
+                }) => error_response(error),
+                _ => json!({
+                    "errid": 1,
+                    "errtype": "apierr",
+                    "errmsg": format!("No recorded response for {}", request.cmd),
+                }),
+            };
+            responses.push(with_uuid(response, request));
+        }
+
+        let result = match failure {
+            Some(error) => Err(error),
+            None => Ok(json!({ "_rcmd": "pipeline", "@rcmds": responses })),
+        };
+
+        std::future::ready(result)
+    }
+}
+
+/// The recorded response, readdressed to the request being replayed
+fn with_uuid(mut response: Value, request: &ApiRequest) -> Value {
+    if let Some(map) = response.as_object_mut() {
+        map.insert("_uuid".to_string(), json!(request.uuid));
+        map.insert("_rcmd".to_string(), json!(request.cmd));
+    }
+
+    response
+}
+
+/// A response that fails with the same error a recorded request failed with
+fn error_response(error: &ApiError) -> Value {
+    match error {
+        ApiError::Api {
+            code,
+            errtype,
+            message,
+        } => json!({ "errid": code, "errtype": errtype, "errmsg": message }),
+        ApiError::Ise { code, message } => {
+            json!({ "errid": code, "errtype": "iseerr", "errmsg": message })
+        }
+        ApiError::Missing { code, message } => {
+            json!({ "errid": code, "errtype": "missing", "errmsg": message })
+        }
+        ApiError::Transport { message } => {
+            json!({ "errid": 1, "errtype": "apierr", "errmsg": message })
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::dispatch::transport::block_on;
+
+    #[test]
+    fn test_record_and_replay() {
+        let request = |uuid: u32, pid: &str| ApiRequest {
+            cmd: "appProductGet".to_string(),
+            params: [("pid".to_string(), json!(pid))].into_iter().collect(),
+            tag: None,
+            uuid: Some(uuid),
+            priority: Default::default(),
+        };
+
+        let mut recorder = Recorder::new(2);
+        recorder.sent(QueueType::Mutable, &request(1000, "A"), 1);
+        recorder.sent(QueueType::Mutable, &request(1001, "B"), 1);
+        recorder.sent(QueueType::Mutable, &request(1002, "C"), 1);
+        recorder.answered(1000, &json!({ "pid": "A" }));
+        recorder.failed(
+            1001,
+            &ApiError::Transport {
+                message: "offline".to_string(),
+            },
+        );
+        recorder.answered(1002, &json!({ "pid": "C" }));
+        recorder.answered(1002, &json!({ "pid": "late" }));
+
+        // Only the newest two are kept
+        let jsonl = recorder.to_jsonl();
+        assert_eq!(jsonl.lines().count(), 2);
+        let exchanges = parse_recording(&jsonl).unwrap();
+        assert_eq!(exchanges[0].seq, 2);
+        assert_eq!(exchanges[1].response, Some(json!({ "pid": "C" })));
+
+        let replay = ReplayTransport::new(exchanges);
+        let batch = |requests: Vec<ApiRequest>| OutgoingBatch {
+            endpoint: String::new(),
+            headers: vec![],
+            body: serde_json::to_value(requests).unwrap(),
+        };
+        let response =
+            block_on(replay.send(&batch(vec![request(5000, "C"), request(5001, "Z")]))).unwrap();
+        assert_eq!(response["@rcmds"][0]["pid"], "C");
+        assert_eq!(response["@rcmds"][0]["_uuid"], 5000);
+        assert_eq!(response["@rcmds"][1]["errtype"], "apierr");
+        assert!(block_on(replay.send(&batch(vec![request(5002, "B")]))).is_err());
+        assert_eq!(replay.remaining(), 0);
+
+        // Credentials never make it into the log, and replay still matches
+        let mut login = request(1003, "L");
+        login.cmd = "appBuyerLogin".to_string();
+        login
+            .params
+            .insert("password".to_string(), json!("hunter2"));
+        recorder.sent(QueueType::Immutable, &login, 2);
+        recorder.answered(1003, &json!({ "cid": 1, "authtoken": "abc123" }));
+        let jsonl = recorder.to_jsonl();
+        assert!(!jsonl.contains("hunter2") && !jsonl.contains("abc123"));
+        let replay = ReplayTransport::from_jsonl(&jsonl).unwrap();
+        let response = block_on(replay.send(&batch(vec![login]))).unwrap();
+        assert_eq!(response["@rcmds"][0]["cid"], 1);
+
+        recorder.sent(QueueType::Mutable, &request(1004, "D"), 3);
+        recorder.cancelled(1004);
+        assert_eq!(recorder.in_flight(), 0);
+    }
+}
@@ -0,0 +1,102 @@
+use serde_json::Value;
+use std::collections::HashMap;
+
+/// What sensitive values are replaced with
+pub const REDACTED: &str = "[redacted]";
+
+/// Whether a param or response field holds a credential or payment details:
+/// passwords, tokens, the `payment/*` checkout fields and `@PAYMENTS`, whose
+/// entries carry card numbers, CVVs and bank accounts
+pub fn is_sensitive_key(key: &str) -> bool {
+    let key = key.to_ascii_lowercase();
+
+    key.contains("password")
+        || key.contains("token")
+        || key.contains("secret")
+        || key.starts_with("payment/")
+        || key == "@payments"
+}
+
+/// Whether any param, at any depth, is sensitive
+pub fn has_sensitive(params: &HashMap<String, Value>) -> bool {
+    params
+        .iter()
+        .any(|(key, value)| is_sensitive_key(key) || value_has_sensitive(value))
+}
+
+/// Replace the value of every sensitive field, at any depth, with `REDACTED`
+pub fn redact(value: &mut Value) {
+    match value {
+        Value::Object(map) => {
+            for (key, value) in map.iter_mut() {
+                if is_sensitive_key(key) {
+                    *value = Value::String(REDACTED.to_string());
+                } else {
+                    redact(value);
+                }
+            }
+        }
+        Value::Array(values) => values.iter_mut().for_each(redact),
+        _ => {}
+    }
+}
+
+/// A copy of request params with every sensitive value replaced
+pub fn redact_params(params: &HashMap<String, Value>) -> HashMap<String, Value> {
+    let mut value = Value::Object(params.clone().into_iter().collect());
+    redact(&mut value);
+
+    match value {
+        Value::Object(map) => map.into_iter().collect(),
+        _ => HashMap::new(),
+    }
+}
+
+fn value_has_sensitive(value: &Value) -> bool {
+    match value {
+        Value::Object(map) => map
+            .iter()
+            .any(|(key, value)| is_sensitive_key(key) || value_has_sensitive(value)),
+        Value::Array(values) => values.iter().any(value_has_sensitive),
+        _ => false,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    #[test]
+    fn test_sensitive_params_and_redaction() {
+        let login = HashMap::from([
+            ("login".to_string(), json!("buyer@example.com")),
+            ("password".to_string(), json!("hunter2")),
+        ]);
+        assert!(has_sensitive(&login));
+
+        let order = HashMap::from([
+            ("_cartid".to_string(), json!("CART1")),
+            (
+                "@PAYMENTS".to_string(),
+                json!(["insert?TN=CREDIT&CC=4111111111111111&CV=123"]),
+            ),
+        ]);
+        assert!(has_sensitive(&order));
+        let nested = HashMap::from([("address".to_string(), json!({ "_authtoken": "abc" }))]);
+        assert!(has_sensitive(&nested));
+
+        let product = HashMap::from([("pid".to_string(), json!("TEST"))]);
+        assert!(!has_sensitive(&product));
+
+        let redacted = redact_params(&login);
+        assert_eq!(redacted["password"], REDACTED);
+        assert_eq!(redacted["login"], "buyer@example.com");
+        assert_eq!(redact_params(&order)["@PAYMENTS"], REDACTED);
+
+        let mut response = json!({ "@rcmds": [{ "authtoken": "abc123", "userid": "admin" }] });
+        redact(&mut response);
+        assert_eq!(response["@rcmds"][0]["authtoken"], REDACTED);
+        assert_eq!(response["@rcmds"][0]["userid"], "admin");
+    }
+}
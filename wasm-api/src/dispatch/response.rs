@@ -0,0 +1,121 @@
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+
+use super::error::{ApiError, ApiMessage};
+use super::group::GroupResolution;
+
+/// A callback that should be fired on the JS side once a response has been handled.
+/// Mirrors the `_rtag` data legacy `handleResponse_defaultAction` passed into callbacks.
+/// When `error` is set the callback's error handler should run instead of its success handler.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct CallbackInvocation {
+    pub uuid: u32,
+    pub cmd: String,
+    pub callback: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub extension: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub datapointer: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub error: Option<ApiError>,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub messages: Vec<ApiMessage>,
+}
+
+/// An error with no callback to handle it. The UI should show it as a global
+/// message (legacy `throwMessage`). `uuid` is unset for errors on the pipe itself.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct UnhandledError {
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub uuid: Option<u32>,
+    pub error: ApiError,
+}
+
+/// Everything the JS side needs to act on after a response has been processed
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct ResponseReport {
+    pub callbacks: Vec<CallbackInvocation>,
+    pub unhandled: Vec<UnhandledError>,
+    /// Requests that failed but have been scheduled to be sent again
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub retrying: Vec<u32>,
+    /// Pipeline groups whose members have all finished
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub groups: Vec<GroupResolution>,
+    /// The admin token expired (errid 10) and the admin credentials were dropped
+    #[serde(default, skip_serializing_if = "is_false")]
+    pub session_expired: bool,
+}
+
+impl ResponseReport {
+    /// Append another report's entries to this one
+    pub fn extend(&mut self, other: ResponseReport) {
+        self.callbacks.extend(other.callbacks);
+        self.unhandled.extend(other.unhandled);
+        self.retrying.extend(other.retrying);
+        self.groups.extend(other.groups);
+        self.session_expired |= other.session_expired;
+    }
+}
+
+fn is_false(value: &bool) -> bool {
+    !*value
+}
+
+/// Split an API response into its individual command responses.
+/// Handles pipelined responses (`_rcmd: "pipeline"` with `@rcmds`), a bare array
+/// of responses and a solo (non-pipelined) response.
+pub fn command_responses(response: Value) -> Vec<Value> {
+    match response {
+        Value::Array(entries) => entries,
+        Value::Object(mut map) => {
+            if map.get("_rcmd").and_then(Value::as_str) == Some("pipeline") {
+                match map.remove("@rcmds") {
+                    Some(Value::Array(entries)) => entries,
+                    _ => vec![],
+                }
+            } else {
+                vec![Value::Object(map)]
+            }
+        }
+        _ => vec![],
+    }
+}
+
+/// Read the `_uuid` of a response. The API echoes it back either as a number or a string.
+pub fn response_uuid(response: &Value) -> Option<u32> {
+    match response.get("_uuid")? {
+        Value::Number(n) => n.as_u64().and_then(|n| u32::try_from(n).ok()),
+        Value::String(s) => s.parse().ok(),
+        _ => None,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    #[test]
+    fn test_command_responses_pipeline() {
+        let response = json!({
+            "_uuid": 999,
+            "_rcmd": "pipeline",
+            "@rcmds": [
+                { "_uuid": 1000, "_rcmd": "appProductGet", "pid": "TEST" },
+                { "_uuid": "1001", "_rcmd": "cartDetail" }
+            ]
+        });
+
+        let entries = command_responses(response);
+        assert_eq!(entries.len(), 2);
+        assert_eq!(response_uuid(&entries[0]), Some(1000));
+        assert_eq!(response_uuid(&entries[1]), Some(1001));
+    }
+
+    #[test]
+    fn test_command_responses_solo() {
+        let entries = command_responses(json!({ "_uuid": 1000, "_rcmd": "appCartCreate" }));
+        assert_eq!(entries.len(), 1);
+    }
+}
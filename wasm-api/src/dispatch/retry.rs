@@ -0,0 +1,170 @@
+use serde::{Deserialize, Serialize};
+
+use super::error::ApiError;
+use super::{ApiRequest, QueueType};
+
+/// How a queue retries requests that failed without a definitive answer
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+pub struct RetryPolicy {
+    /// Total sends allowed, including the first. 1 disables retries.
+    pub max_attempts: u32,
+    pub base_delay_ms: u64,
+    pub max_delay_ms: u64,
+    /// Fraction of the backoff delay that is randomised, from 0.0 to 1.0
+    pub jitter: f64,
+}
+
+impl RetryPolicy {
+    pub fn none() -> RetryPolicy {
+        RetryPolicy {
+            max_attempts: 1,
+            base_delay_ms: 0,
+            max_delay_ms: 0,
+            jitter: 0.0,
+        }
+    }
+
+    /// Default policy for each queue. Immutable requests (cart, checkout) get the
+    /// most attempts; passive requests are fire-and-forget and never retried.
+    pub fn for_queue(queue_type: QueueType) -> RetryPolicy {
+        match queue_type {
+            QueueType::Immutable => RetryPolicy {
+                max_attempts: 5,
+                base_delay_ms: 500,
+                max_delay_ms: 30_000,
+                jitter: 0.5,
+            },
+            QueueType::Mutable => RetryPolicy {
+                max_attempts: 3,
+                base_delay_ms: 250,
+                max_delay_ms: 5_000,
+                jitter: 0.5,
+            },
+            QueueType::Passive => RetryPolicy::none(),
+        }
+    }
+
+    /// Backoff before the send following `attempt` (1-based): the base delay doubled
+    /// per attempt, capped at `max_delay_ms`, with up to `jitter` of it taken off at random
+    pub fn delay_ms(&self, attempt: u32, rng: &mut Xorshift) -> u64 {
+        let exponent = attempt.saturating_sub(1).min(32);
+        let delay = self
+            .base_delay_ms
+            .saturating_mul(1 << exponent)
+            .min(self.max_delay_ms);
+        let jitter = (delay as f64 * self.jitter.clamp(0.0, 1.0) * rng.next_f64()) as u64;
+
+        delay - jitter
+    }
+}
+
+/// Whether an error leaves the outcome of a request unknown, so that sending it
+/// again may succeed. Errors the API reported for the command itself are final.
+pub fn is_retryable(error: &ApiError) -> bool {
+    matches!(error, ApiError::Transport { .. } | ApiError::Ise { .. })
+}
+
+/// Whether a command can be sent twice with the same effect as sending it once.
+/// Reads are always idempotent; of the writes only those that set state are.
+/// Commands like `cartItemAppend` or `cartOrderCreate` are never re-sent automatically.
+pub fn is_idempotent(cmd: &str) -> bool {
+    const IDEMPOTENT_WRITES: [&str; 4] = [
+        "cartSet",
+        "cartItemUpdate",
+        "cartCouponAdd",
+        "appBuyerLogin",
+    ];
+    const READ_SUFFIXES: [&str; 5] = ["Get", "Detail", "List", "Search", "Exists"];
+
+    IDEMPOTENT_WRITES.contains(&cmd) || READ_SUFFIXES.iter().any(|suffix| cmd.ends_with(suffix))
+}
+
+/// A request that ran out of attempts. Kept for the UI to inspect or requeue.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct DeadLetter {
+    pub uuid: u32,
+    pub queue_type: QueueType,
+    pub cmd: String,
+    pub attempts: u32,
+    pub error: ApiError,
+}
+
+impl DeadLetter {
+    pub fn new(
+        uuid: u32,
+        queue_type: QueueType,
+        request: &ApiRequest,
+        attempts: u32,
+        error: ApiError,
+    ) -> DeadLetter {
+        DeadLetter {
+            uuid,
+            queue_type,
+            cmd: request.cmd.clone(),
+            attempts,
+            error,
+        }
+    }
+}
+
+/// Small deterministic PRNG for backoff jitter
+#[derive(Debug, Clone, Copy)]
+pub struct Xorshift(u64);
+
+impl Xorshift {
+    pub fn new(seed: u64) -> Xorshift {
+        // A zero state would only ever produce zeros
+        Xorshift(seed.max(1))
+    }
+
+    pub fn next_u64(&mut self) -> u64 {
+        self.0 ^= self.0 << 13;
+        self.0 ^= self.0 >> 7;
+        self.0 ^= self.0 << 17;
+        self.0
+    }
+
+    /// A value in `[0, 1)`
+    pub fn next_f64(&mut self) -> f64 {
+        (self.next_u64() >> 11) as f64 / (1u64 << 53) as f64
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_backoff_and_classification() {
+        let policy = RetryPolicy {
+            max_attempts: 5,
+            base_delay_ms: 100,
+            max_delay_ms: 1000,
+            jitter: 0.0,
+        };
+        let mut rng = Xorshift::new(42);
+        assert_eq!(policy.delay_ms(1, &mut rng), 100);
+        assert_eq!(policy.delay_ms(3, &mut rng), 400);
+        assert_eq!(policy.delay_ms(10, &mut rng), 1000);
+
+        let jittered = RetryPolicy {
+            jitter: 0.5,
+            ..policy
+        };
+        for _ in 0..100 {
+            let delay = jittered.delay_ms(2, &mut rng);
+            assert!((100..=200).contains(&delay));
+        }
+
+        assert!(is_idempotent("appProductGet"));
+        assert!(is_idempotent("cartItemUpdate"));
+        assert!(!is_idempotent("cartOrderCreate"));
+        assert!(is_retryable(&ApiError::Transport {
+            message: "offline".to_string()
+        }));
+        assert!(!is_retryable(&ApiError::Missing {
+            code: "MVC-M-100".to_string(),
+            message: String::new(),
+        }));
+    }
+}
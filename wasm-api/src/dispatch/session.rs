@@ -0,0 +1,158 @@
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+
+/// What an admin login (`authAdminLogin`, `authNewAccountCreate`) returns
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct AdminCredentials {
+    pub userid: String,
+    pub username: String,
+    pub deviceid: String,
+    pub authtoken: String,
+    /// When the token stops being sent, in seconds since the epoch
+    pub expires_at: u64,
+}
+
+/// A buyer signed in with `appBuyerLogin`. The login itself is tied to the session
+/// server side, so nothing extra is sent with requests.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct BuyerLogin {
+    pub login: String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub cid: Option<String>,
+}
+
+/// Who is making requests (legacy `_app.vars._session`, `authtoken`, ...).
+/// Decorates every outgoing batch with the headers from `model.setHeader`.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Session {
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub session_id: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub client_id: Option<String>,
+    /// The domain in focus
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub domain: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub version: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub admin: Option<AdminCredentials>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub buyer: Option<BuyerLogin>,
+}
+
+impl Session {
+    /// Headers for a batch sent at `now`. Only headers the API whitelists are sent;
+    /// the admin ones only while the admin token is valid.
+    pub fn headers(&self, now: u64) -> Vec<(String, String)> {
+        let admin = self.valid_admin(now);
+        let fields = [
+            ("x-session", self.session_id.as_deref()),
+            ("x-clientid", self.client_id.as_deref()),
+            ("x-domain", self.domain.as_deref()),
+            ("x-version", self.version.as_deref()),
+            ("x-userid", admin.map(|a| a.userid.as_str())),
+            ("x-deviceid", admin.map(|a| a.deviceid.as_str())),
+            ("x-authtoken", admin.map(|a| a.authtoken.as_str())),
+        ];
+
+        fields
+            .into_iter()
+            .filter_map(|(name, value)| {
+                let value = value.filter(|v| !v.is_empty())?;
+                Some((name.to_string(), value.to_string()))
+            })
+            .collect()
+    }
+
+    /// The admin credentials, unless the token has expired
+    pub fn valid_admin(&self, now: u64) -> Option<&AdminCredentials> {
+        self.admin.as_ref().filter(|admin| now < admin.expires_at)
+    }
+
+    /// Keep the credentials from a successful admin login response.
+    /// Returns false if the response does not carry a token.
+    pub fn admin_login(&mut self, response: &Value, expires_at: u64) -> bool {
+        let field = |key: &str| {
+            response
+                .get(key)
+                .and_then(Value::as_str)
+                .unwrap_or_default()
+                .to_string()
+        };
+        let authtoken = field("authtoken");
+        if authtoken.is_empty() {
+            return false;
+        }
+
+        self.admin = Some(AdminCredentials {
+            userid: field("userid").to_lowercase(),
+            username: field("username").to_lowercase(),
+            deviceid: field("deviceid"),
+            authtoken,
+            expires_at,
+        });
+
+        true
+    }
+
+    /// Record a successful `appBuyerLogin` for `login`
+    pub fn buyer_login(&mut self, login: &str, response: &Value) {
+        let cid = match response.get("cid") {
+            Some(Value::String(cid)) => Some(cid.clone()),
+            Some(Value::Number(cid)) => Some(cid.to_string()),
+            _ => None,
+        };
+
+        self.buyer = Some(BuyerLogin {
+            login: login.to_string(),
+            cid,
+        });
+    }
+
+    /// Forget both logins. The session ID and client settings are kept.
+    pub fn logout(&mut self) {
+        self.admin = None;
+        self.buyer = None;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    #[test]
+    fn test_headers_follow_login_state() {
+        let mut session = Session {
+            session_id: Some("S1".to_string()),
+            version: Some("201411".to_string()),
+            ..Session::default()
+        };
+        let names = |session: &Session, now| -> Vec<String> {
+            session
+                .headers(now)
+                .into_iter()
+                .map(|(name, _)| name)
+                .collect()
+        };
+        assert_eq!(names(&session, 0), vec!["x-session", "x-version"]);
+
+        assert!(!session.admin_login(&json!({ "errid": 1 }), 100));
+        assert!(session.admin_login(
+            &json!({ "authtoken": "T", "userid": "ACME", "username": "Bob", "deviceid": "D" }),
+            100
+        ));
+        assert_eq!(session.admin.as_ref().unwrap().userid, "acme");
+        assert!(session
+            .headers(50)
+            .contains(&("x-authtoken".to_string(), "T".to_string())));
+        assert_eq!(names(&session, 100), vec!["x-session", "x-version"]);
+
+        session.buyer_login("buyer@example.com", &json!({ "cid": 42 }));
+        assert_eq!(session.buyer.as_ref().unwrap().cid.as_deref(), Some("42"));
+        session.logout();
+        assert_eq!(session.admin, None);
+        assert_eq!(session.buyer, None);
+        assert_eq!(session.session_id.as_deref(), Some("S1"));
+    }
+}
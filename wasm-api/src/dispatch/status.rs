@@ -0,0 +1,49 @@
+use wasm_bindgen::prelude::*;
+use serde::{Deserialize, Serialize};
+
+/// Lifecycle of a dispatch (legacy `_tag.status`)
+#[wasm_bindgen]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize)]
+pub enum RequestStatus {
+    Queued,     // Waiting in its queue
+    Requesting, // Sent, waiting on a response
+    Completed,  // Response handled
+    Cancelled,  // Aborted before a response was handled
+    Error,      // Request or response failed
+}
+
+impl RequestStatus {
+    /// Whether a dispatch may move from this status to `next`
+    pub fn can_transition_to(self, next: RequestStatus) -> bool {
+        use RequestStatus::*;
+
+        matches!(
+            (self, next),
+            (Queued, Requesting)
+                | (Queued, Cancelled)
+                | (Requesting, Completed)
+                | (Requesting, Cancelled)
+                | (Requesting, Error)
+                | (Error, Queued)
+        )
+    }
+
+    /// Completed and cancelled dispatches never change again
+    pub fn is_terminal(self) -> bool {
+        matches!(self, RequestStatus::Completed | RequestStatus::Cancelled)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_status_transitions() {
+        assert!(RequestStatus::Queued.can_transition_to(RequestStatus::Requesting));
+        assert!(RequestStatus::Error.can_transition_to(RequestStatus::Queued));
+        assert!(!RequestStatus::Completed.can_transition_to(RequestStatus::Queued));
+        assert!(!RequestStatus::Cancelled.can_transition_to(RequestStatus::Requesting));
+        assert!(!RequestStatus::Queued.can_transition_to(RequestStatus::Completed));
+    }
+}
@@ -0,0 +1,68 @@
+use std::collections::HashMap;
+
+/// A string key/value store (localStorage, sessionStorage or an in-memory stand-in)
+pub trait StorageBackend {
+    fn get(&self, key: &str) -> Option<String>;
+    fn set(&mut self, key: &str, value: String);
+    fn remove(&mut self, key: &str);
+}
+
+/// In-memory storage. Used natively and whenever browser storage is unavailable.
+#[derive(Debug, Default, Clone)]
+pub struct MemoryStorage {
+    items: HashMap<String, String>,
+}
+
+impl MemoryStorage {
+    pub fn new() -> MemoryStorage {
+        MemoryStorage::default()
+    }
+}
+
+impl StorageBackend for MemoryStorage {
+    fn get(&self, key: &str) -> Option<String> {
+        self.items.get(key).cloned()
+    }
+
+    fn set(&mut self, key: &str, value: String) {
+        self.items.insert(key.to_string(), value);
+    }
+
+    fn remove(&mut self, key: &str) {
+        self.items.remove(key);
+    }
+}
+
+/// Browser localStorage/sessionStorage (legacy `writeLocal`/`readLocal`)
+pub struct WebStorage {
+    storage: web_sys::Storage,
+}
+
+impl WebStorage {
+    /// `window.localStorage`, if the browser allows it
+    pub fn local() -> Option<WebStorage> {
+        let storage = web_sys::window()?.local_storage().ok()??;
+        Some(WebStorage { storage })
+    }
+
+    /// `window.sessionStorage`, if the browser allows it
+    pub fn session() -> Option<WebStorage> {
+        let storage = web_sys::window()?.session_storage().ok()??;
+        Some(WebStorage { storage })
+    }
+}
+
+impl StorageBackend for WebStorage {
+    fn get(&self, key: &str) -> Option<String> {
+        self.storage.get_item(key).ok().flatten()
+    }
+
+    fn set(&mut self, key: &str, value: String) {
+        // Quota errors are ignored, same as legacy writeLocal
+        let _ = self.storage.set_item(key, &value);
+    }
+
+    fn remove(&mut self, key: &str) {
+        let _ = self.storage.remove_item(key);
+    }
+}
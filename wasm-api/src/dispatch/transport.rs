@@ -0,0 +1,238 @@
+use wasm_bindgen::prelude::*;
+use serde::{Deserialize, Serialize};
+use serde_json::{json, Value};
+use std::cell::RefCell;
+use std::collections::HashMap;
+use std::future::Future;
+
+use super::error::ApiError;
+
+/// A batch ready to go over the wire
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct OutgoingBatch {
+    pub endpoint: String,
+    pub headers: Vec<(String, String)>,
+    pub body: Value,
+}
+
+/// What became of a batch sent from JS with `fetch_batch`: the requests it held
+/// and the response, or why there was none
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct BatchOutcome {
+    pub uuids: Vec<u32>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub response: Option<Value>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub error: Option<ApiError>,
+}
+
+impl BatchOutcome {
+    pub fn new(uuids: Vec<u32>, result: Result<Value, ApiError>) -> BatchOutcome {
+        let (response, error) = match result {
+            Ok(response) => (Some(response), None),
+            Err(error) => (None, Some(error)),
+        };
+
+        BatchOutcome {
+            uuids,
+            response,
+            error,
+        }
+    }
+
+    /// The requests and the response or error, as `complete_batch` takes them
+    pub fn into_parts(self) -> (Vec<u32>, Result<Value, ApiError>) {
+        let result = match (self.response, self.error) {
+            (_, Some(error)) => Err(error),
+            (Some(response), None) => Ok(response),
+            (None, None) => Err(ApiError::Transport {
+                message: "The batch got no response".to_string(),
+            }),
+        };
+
+        (self.uuids, result)
+    }
+}
+
+/// Sends a batch to the JSON API and returns the parsed response
+pub trait Transport {
+    fn send(&self, batch: &OutgoingBatch) -> impl Future<Output = Result<Value, ApiError>>;
+}
+
+/// Browser `fetch` transport
+#[derive(Debug, Default, Clone, Copy)]
+pub struct FetchTransport;
+
+impl Transport for FetchTransport {
+    fn send(&self, batch: &OutgoingBatch) -> impl Future<Output = Result<Value, ApiError>> {
+        let batch = batch.clone();
+
+        async move {
+            let body = serde_json::to_string(&batch.body).map_err(|e| ApiError::Transport {
+                message: format!("Failed to serialize batch: {}", e),
+            })?;
+
+            let init = web_sys::RequestInit::new();
+            init.set_method("POST");
+            init.set_body(&JsValue::from_str(&body));
+
+            let request = web_sys::Request::new_with_str_and_init(&batch.endpoint, &init)
+                .map_err(js_error)?;
+            for (name, value) in &batch.headers {
+                request.headers().set(name, value).map_err(js_error)?;
+            }
+
+            let window = web_sys::window().ok_or_else(|| ApiError::Transport {
+                message: "No window available for fetch".to_string(),
+            })?;
+            let response: web_sys::Response =
+                wasm_bindgen_futures::JsFuture::from(window.fetch_with_request(&request))
+                    .await
+                    .map_err(js_error)?
+                    .dyn_into()
+                    .map_err(js_error)?;
+
+            if !response.ok() {
+                return Err(ApiError::Transport {
+                    message: format!("HTTP {} from {}", response.status(), batch.endpoint),
+                });
+            }
+
+            let text = wasm_bindgen_futures::JsFuture::from(response.text().map_err(js_error)?)
+                .await
+                .map_err(js_error)?
+                .as_string()
+                .unwrap_or_default();
+
+            serde_json::from_str(&text).map_err(|e| ApiError::Transport {
+                message: format!("Failed to parse response: {}", e),
+            })
+        }
+    }
+}
+
+fn js_error(error: JsValue) -> ApiError {
+    ApiError::Transport {
+        message: error.as_string().unwrap_or_else(|| format!("{:?}", error)),
+    }
+}
+
+type MockHandler = Box<dyn Fn(&Value) -> Value>;
+
+/// In-process JSON API backend. Answers each request in a batch with the handler
+/// registered for its `_cmd` and records every batch it receives.
+#[derive(Default)]
+pub struct MockTransport {
+    handlers: HashMap<String, MockHandler>,
+    failure: RefCell<Option<ApiError>>,
+    sent: RefCell<Vec<OutgoingBatch>>,
+}
+
+impl MockTransport {
+    pub fn new() -> MockTransport {
+        MockTransport::default()
+    }
+
+    /// Answer requests for `cmd` with the value returned by `handler`.
+    /// `_uuid` and `_rcmd` are filled in automatically.
+    pub fn on(mut self, cmd: &str, handler: impl Fn(&Value) -> Value + 'static) -> MockTransport {
+        self.handlers.insert(cmd.to_string(), Box::new(handler));
+        self
+    }
+
+    /// Fail the next send as if the network request itself had failed
+    pub fn fail_next(&self, error: ApiError) {
+        *self.failure.borrow_mut() = Some(error);
+    }
+
+    /// Every batch sent so far, in order
+    pub fn sent(&self) -> Vec<OutgoingBatch> {
+        self.sent.borrow().clone()
+    }
+
+    fn respond(&self, request: &Value) -> Value {
+        let cmd = request
+            .get("_cmd")
+            .and_then(Value::as_str)
+            .unwrap_or_default();
+
+        let mut response = match self.handlers.get(cmd) {
+            Some(handler) => handler(request),
+            None => json!({
+                "errid": 1,
+                "errtype": "apierr",
+                "errmsg": format!("No mock handler for {}", cmd),
+            }),
+        };
+        if let Some(map) = response.as_object_mut() {
+            map.insert(
+                "_uuid".to_string(),
+                request.get("_uuid").cloned().unwrap_or(Value::Null),
+            );
+            map.insert("_rcmd".to_string(), json!(cmd));
+        }
+
+        response
+    }
+}
+
+impl Transport for MockTransport {
+    fn send(&self, batch: &OutgoingBatch) -> impl Future<Output = Result<Value, ApiError>> {
+        self.sent.borrow_mut().push(batch.clone());
+
+        let result = match self.failure.borrow_mut().take() {
+            Some(error) => Err(error),
+            None => {
+                let requests = batch.body.as_array().cloned().unwrap_or_default();
+                let responses: Vec<Value> = requests.iter().map(|r| self.respond(r)).collect();
+                Ok(json!({ "_rcmd": "pipeline", "@rcmds": responses }))
+            }
+        };
+
+        std::future::ready(result)
+    }
+}
+
+/// Drive a future to completion in tests. Busy-polls, so only suitable for
+/// transports that never wait on I/O, such as `MockTransport`.
+#[cfg(test)]
+pub(crate) fn block_on<F: Future>(future: F) -> F::Output {
+    use std::task::{Context, Poll, Waker};
+
+    let mut future = std::pin::pin!(future);
+    let mut cx = Context::from_waker(Waker::noop());
+    loop {
+        if let Poll::Ready(output) = future.as_mut().poll(&mut cx) {
+            return output;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_mock_transport_answers_by_cmd() {
+        let transport = MockTransport::new().on("appCartCreate", |_| json!({ "_cartid": "CART1" }));
+        let batch = OutgoingBatch {
+            endpoint: "/jsonapi/".to_string(),
+            headers: vec![],
+            body: json!([
+                { "_cmd": "appCartCreate", "_uuid": 1000 },
+                { "_cmd": "unknownCmd", "_uuid": 1001 }
+            ]),
+        };
+
+        let response = block_on(transport.send(&batch)).unwrap();
+        assert_eq!(response["@rcmds"][0]["_cartid"], "CART1");
+        assert_eq!(response["@rcmds"][0]["_uuid"], 1000);
+        assert_eq!(response["@rcmds"][1]["errtype"], "apierr");
+        assert_eq!(transport.sent().len(), 1);
+
+        transport.fail_next(ApiError::Transport {
+            message: "offline".to_string(),
+        });
+        assert!(block_on(transport.send(&batch)).is_err());
+    }
+}
@@ -1,6 +1,7 @@
 use wasm_bindgen::prelude::*;
 
 pub mod dispatch;
+pub mod commands;
 pub mod product;
 pub mod cart;
 pub mod validation;
@@ -15,10 +16,6 @@ pub use utils::*;
 
 #[wasm_bindgen(start)]
 pub fn init() {
-    // Set panic hook for better error messages in browser console
-    #[cfg(feature = "console_error_panic_hook")]
-    console_error_panic_hook::set_once();
-
     utils::log("AnyCommerce WASM module initialized");
 }
 
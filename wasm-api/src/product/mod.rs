@@ -1,6 +1,9 @@
 use wasm_bindgen::prelude::*;
 use serde::{Deserialize, Serialize};
 use std::collections::HashMap;
+use thiserror::Error;
+
+use crate::utils::flag;
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct Variation {
@@ -8,6 +11,9 @@ pub struct Variation {
     pub prompt: String,
     #[serde(rename = "type")]
     pub variation_type: String,
+    /// Inventoriable: each option is stocked as its own SKU
+    #[serde(default, with = "flag")]
+    pub inv: bool,
     #[serde(rename = "@options")]
     pub options: Vec<VariationOption>,
 }
@@ -41,7 +47,70 @@ pub struct Product {
     pub attribs: HashMap<String, serde_json::Value>,
 }
 
+/// Why a product lookup or computation failed
+#[derive(Debug, Clone, PartialEq, Eq, Error)]
+pub enum ProductError {
+    #[error("Product {0} not found")]
+    NotFound(String),
+    #[error("Missing selection for variation {0}")]
+    MissingSelection(String),
+    #[error("Inventory not found for SKU {0}")]
+    NoInventory(String),
+    #[error("Attribute {attr} not found for product {pid}")]
+    NoAttribute { pid: String, attr: String },
+}
+
+impl From<ProductError> for JsValue {
+    fn from(error: ProductError) -> Self {
+        JsValue::from_str(&error.to_string())
+    }
+}
+
+impl Product {
+    /// SKU for a set of variation selections (variation id -> option value).
+    /// Each inventoriable variation adds its id and the selected value, e.g.
+    /// "TEST:0209" for option 09 of variation 02; the others are not part of it.
+    pub fn sku(&self, selections: &HashMap<String, String>) -> Result<String, ProductError> {
+        let mut sku = self.pid.clone();
+        for variation in self.variations.iter().filter(|v| v.inv) {
+            match selections.get(&variation.id) {
+                Some(selected_value) => {
+                    sku.push(':');
+                    sku.push_str(&variation.id);
+                    sku.push_str(selected_value);
+                }
+                None => return Err(ProductError::MissingSelection(variation.id.clone())),
+            }
+        }
+
+        Ok(sku)
+    }
+
+    /// Base price plus the price modifiers of the selected options
+    pub fn price(&self, selections: &HashMap<String, String>) -> f64 {
+        let base_price: f64 = self
+            .attribs
+            .get("zoovy:base_price")
+            .and_then(|v| v.as_str())
+            .and_then(|s| s.parse().ok())
+            .unwrap_or(0.0);
+
+        let modifiers: f64 = self
+            .variations
+            .iter()
+            .filter_map(|variation| {
+                let selected_value = selections.get(&variation.id)?;
+                let option = variation.options.iter().find(|o| &o.v == selected_value)?;
+                option.price_mod
+            })
+            .sum();
+
+        base_price + modifiers
+    }
+}
+
 #[wasm_bindgen]
+#[derive(Default)]
 pub struct ProductProcessor {
     products: HashMap<String, Product>,
 }
@@ -50,9 +119,7 @@ pub struct ProductProcessor {
 impl ProductProcessor {
     #[wasm_bindgen(constructor)]
     pub fn new() -> ProductProcessor {
-        ProductProcessor {
-            products: HashMap::new(),
-        }
+        ProductProcessor::default()
     }
 
     /// Load a product from JSON
@@ -60,76 +127,28 @@ impl ProductProcessor {
         let product: Product = serde_wasm_bindgen::from_value(product_json)
             .map_err(|e| JsValue::from_str(&format!("Failed to parse product: {}", e)))?;
 
-        let pid = product.pid.clone();
-        self.products.insert(pid.clone(), product);
-
-        Ok(pid)
+        Ok(self.add_product(product))
     }
 
     /// Generate SKU from base PID and variation selections
-    /// Example: calculate_sku("TEST", {0: "00", 1: "01"}) -> "TEST:0001"
+    /// Example: calculate_sku("TEST", {02: "09"}) -> "TEST:0209"
     pub fn calculate_sku(&self, pid: &str, selections: JsValue) -> Result<String, JsValue> {
-        let selections: HashMap<String, String> = serde_wasm_bindgen::from_value(selections)
-            .map_err(|e| JsValue::from_str(&format!("Failed to parse selections: {}", e)))?;
+        let selections = parse_selections(selections)?;
 
-        let product = self
-            .products
-            .get(pid)
-            .ok_or_else(|| JsValue::from_str(&format!("Product {} not found", pid)))?;
-
-        if product.variations.is_empty() {
-            return Ok(pid.to_string());
-        }
-
-        // Build SKU suffix from variation selections
-        let mut sku_parts: Vec<String> = vec![];
-        for variation in &product.variations {
-            if let Some(selected_value) = selections.get(&variation.id) {
-                sku_parts.push(selected_value.clone());
-            } else {
-                return Err(JsValue::from_str(&format!(
-                    "Missing selection for variation {}",
-                    variation.id
-                )));
-            }
-        }
-
-        let sku = if sku_parts.is_empty() {
-            pid.to_string()
-        } else {
-            format!("{}:{}", pid, sku_parts.join(""))
-        };
-
-        Ok(sku)
+        Ok(self.sku(pid, &selections)?)
     }
 
     /// Check if a SKU is available in inventory
     pub fn check_inventory(&self, sku: &str) -> Result<JsValue, JsValue> {
-        // Extract PID from SKU
-        let pid = sku.split(':').next().unwrap_or(sku);
+        let inventory_item = self.inventory(sku)?;
 
-        let product = self
-            .products
-            .get(pid)
-            .ok_or_else(|| JsValue::from_str(&format!("Product {} not found", pid)))?;
-
-        if let Some(inventory_item) = product.inventory.get(sku) {
-            serde_wasm_bindgen::to_value(&inventory_item)
-                .map_err(|e| JsValue::from_str(&format!("Failed to serialize inventory: {}", e)))
-        } else {
-            Err(JsValue::from_str(&format!(
-                "Inventory not found for SKU {}",
-                sku
-            )))
-        }
+        serde_wasm_bindgen::to_value(inventory_item)
+            .map_err(|e| JsValue::from_str(&format!("Failed to serialize inventory: {}", e)))
     }
 
     /// Get all variations for a product
     pub fn get_variations(&self, pid: &str) -> Result<JsValue, JsValue> {
-        let product = self
-            .products
-            .get(pid)
-            .ok_or_else(|| JsValue::from_str(&format!("Product {} not found", pid)))?;
+        let product = self.product(pid)?;
 
         serde_wasm_bindgen::to_value(&product.variations)
             .map_err(|e| JsValue::from_str(&format!("Failed to serialize variations: {}", e)))
@@ -137,58 +156,81 @@ impl ProductProcessor {
 
     /// Get product attribute
     pub fn get_attribute(&self, pid: &str, attr_name: &str) -> Result<JsValue, JsValue> {
-        let product = self
-            .products
-            .get(pid)
-            .ok_or_else(|| JsValue::from_str(&format!("Product {} not found", pid)))?;
+        let value = self.attribute(pid, attr_name)?;
 
-        product
-            .attribs
-            .get(attr_name)
-            .ok_or_else(|| {
-                JsValue::from_str(&format!("Attribute {} not found for product {}", attr_name, pid))
-            })
-            .and_then(|v| {
-                serde_wasm_bindgen::to_value(v)
-                    .map_err(|e| JsValue::from_str(&format!("Failed to serialize attribute: {}", e)))
-            })
+        serde_wasm_bindgen::to_value(value)
+            .map_err(|e| JsValue::from_str(&format!("Failed to serialize attribute: {}", e)))
     }
 
     /// Calculate final price with variation price modifiers
     pub fn calculate_price(&self, pid: &str, selections: JsValue) -> Result<f64, JsValue> {
-        let selections: HashMap<String, String> = serde_wasm_bindgen::from_value(selections)
-            .map_err(|e| JsValue::from_str(&format!("Failed to parse selections: {}", e)))?;
+        let selections = parse_selections(selections)?;
 
-        let product = self
-            .products
+        Ok(self.price(pid, &selections)?)
+    }
+}
+
+impl ProductProcessor {
+    /// Keep a product for later lookups. Returns its PID.
+    pub fn add_product(&mut self, product: Product) -> String {
+        let pid = product.pid.clone();
+        self.products.insert(pid.clone(), product);
+
+        pid
+    }
+
+    pub fn product(&self, pid: &str) -> Result<&Product, ProductError> {
+        self.products
             .get(pid)
-            .ok_or_else(|| JsValue::from_str(&format!("Product {} not found", pid)))?;
+            .ok_or_else(|| ProductError::NotFound(pid.to_string()))
+    }
 
-        // Get base price
-        let base_price: f64 = product
-            .attribs
-            .get("zoovy:base_price")
-            .and_then(|v| v.as_str())
-            .and_then(|s| s.parse().ok())
-            .unwrap_or(0.0);
+    pub fn sku(
+        &self,
+        pid: &str,
+        selections: &HashMap<String, String>,
+    ) -> Result<String, ProductError> {
+        self.product(pid)?.sku(selections)
+    }
 
-        let mut final_price = base_price;
+    pub fn price(
+        &self,
+        pid: &str,
+        selections: &HashMap<String, String>,
+    ) -> Result<f64, ProductError> {
+        Ok(self.product(pid)?.price(selections))
+    }
 
-        // Add variation price modifiers
-        for variation in &product.variations {
-            if let Some(selected_value) = selections.get(&variation.id) {
-                if let Some(option) = variation.options.iter().find(|o| &o.v == selected_value) {
-                    if let Some(price_mod) = option.price_mod {
-                        final_price += price_mod;
-                    }
-                }
-            }
-        }
+    /// Inventory record for a SKU, looked up on the product named by its PID part
+    pub fn inventory(&self, sku: &str) -> Result<&InventoryItem, ProductError> {
+        let pid = sku.split(':').next().unwrap_or(sku);
 
-        Ok(final_price)
+        self.product(pid)?
+            .inventory
+            .get(sku)
+            .ok_or_else(|| ProductError::NoInventory(sku.to_string()))
+    }
+
+    pub fn attribute(
+        &self,
+        pid: &str,
+        attr_name: &str,
+    ) -> Result<&serde_json::Value, ProductError> {
+        self.product(pid)?
+            .attribs
+            .get(attr_name)
+            .ok_or_else(|| ProductError::NoAttribute {
+                pid: pid.to_string(),
+                attr: attr_name.to_string(),
+            })
     }
 }
 
+fn parse_selections(selections: JsValue) -> Result<HashMap<String, String>, JsValue> {
+    serde_wasm_bindgen::from_value(selections)
+        .map_err(|e| JsValue::from_str(&format!("Failed to parse selections: {}", e)))
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -204,10 +246,18 @@ mod tests {
                     "id": "02",
                     "prompt": "Size",
                     "type": "select",
+                    "inv": "1",
                     "@options": [
                         { "v": "00", "prompt": "Small" },
                         { "v": "01", "prompt": "Medium" }
                     ]
+                },
+                {
+                    "id": "A1",
+                    "prompt": "Gift wrap",
+                    "type": "checkbox",
+                    "inv": "0",
+                    "@options": [{ "v": "ON", "prompt": "Gift wrap", "price_mod": 5.0 }]
                 }
             ],
             "@inventory": {},
@@ -216,13 +266,22 @@ mod tests {
             }
         });
 
-        let js_product = serde_wasm_bindgen::to_value(&product_json).unwrap();
-        processor.load_product(js_product).unwrap();
+        processor.add_product(serde_json::from_value(product_json).unwrap());
+
+        let mut selections = HashMap::new();
+        selections.insert("02".to_string(), "00".to_string());
 
-        let selections = serde_json::json!({"02": "00"});
-        let js_selections = serde_wasm_bindgen::to_value(&selections).unwrap();
+        let sku = processor.sku("TEST", &selections).unwrap();
+        assert_eq!(sku, "TEST:0200");
+        assert_eq!(processor.price("TEST", &selections).unwrap(), 99.99);
 
-        let sku = processor.calculate_sku("TEST", js_selections).unwrap();
-        assert_eq!(sku, "TEST:00");
+        // Non-inventoriable options change the price but not the SKU
+        selections.insert("A1".to_string(), "ON".to_string());
+        assert_eq!(processor.sku("TEST", &selections).unwrap(), "TEST:0200");
+        assert_eq!(processor.price("TEST", &selections).unwrap(), 104.99);
+        assert_eq!(
+            processor.sku("TEST", &HashMap::new()),
+            Err(ProductError::MissingSelection("02".to_string()))
+        );
     }
 }
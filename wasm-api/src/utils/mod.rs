@@ -33,17 +33,36 @@ pub fn format_currency(amount: f64, currency: &str) -> String {
 /// Parse currency string to float
 #[wasm_bindgen]
 pub fn parse_currency(value: &str) -> Result<f64, JsValue> {
-    // Remove currency symbols and commas
-    let cleaned = value
-        .replace('$', "")
-        .replace('€', "")
-        .replace('£', "")
-        .replace(',', "");
-
-    cleaned
-        .trim()
-        .parse::<f64>()
-        .map_err(|e| JsValue::from_str(&format!("Failed to parse currency: {}", e)))
+    parse_amount(value).map_err(|e| JsValue::from_str(&format!("Failed to parse currency: {}", e)))
+}
+
+/// Parse an amount such as "$1,234.56", ignoring currency symbols and commas
+pub fn parse_amount(value: &str) -> Result<f64, std::num::ParseFloatError> {
+    let cleaned = value.replace(['$', '€', '£', ','], "");
+
+    cleaned.trim().parse::<f64>()
+}
+
+/// Current time in seconds since the epoch (legacy `_app.u.epochNow`)
+pub fn epoch_now() -> u64 {
+    epoch_now_ms() / 1000
+}
+
+/// Current time in milliseconds since the epoch
+pub fn epoch_now_ms() -> u64 {
+    #[cfg(target_arch = "wasm32")]
+    {
+        js_sys::Date::now() as u64
+    }
+    #[cfg(not(target_arch = "wasm32"))]
+    {
+        use std::time::{SystemTime, UNIX_EPOCH};
+
+        SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map(|d| d.as_millis() as u64)
+            .unwrap_or(0)
+    }
 }
 
 /// Generate a simple unique ID
@@ -59,6 +78,56 @@ pub fn generate_id() -> String {
     format!("{:x}", timestamp)
 }
 
+/// The API takes and returns flags as 1/0
+pub(crate) mod flag {
+    use serde::{Deserialize, Deserializer, Serializer};
+    use serde_json::Value;
+
+    pub fn serialize<S: Serializer>(value: &bool, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.serialize_u8(u8::from(*value))
+    }
+
+    pub fn deserialize<'de, D: Deserializer<'de>>(deserializer: D) -> Result<bool, D::Error> {
+        Ok(match Value::deserialize(deserializer)? {
+            Value::Bool(b) => b,
+            Value::Number(n) => n.as_f64().is_some_and(|n| n != 0.0),
+            Value::String(s) => !matches!(s.as_str(), "" | "0"),
+            _ => false,
+        })
+    }
+}
+
+/// The API sends amounts and quantities as strings ("12.99", "1")
+pub(crate) mod number {
+    use serde::de::{Deserialize, Deserializer, Error};
+    use serde_json::Value;
+    use std::str::FromStr;
+
+    pub fn deserialize<'de, D, T>(deserializer: D) -> Result<T, D::Error>
+    where
+        D: Deserializer<'de>,
+        T: FromStr + Default,
+    {
+        let text = match Value::deserialize(deserializer)? {
+            Value::Number(n) => n.to_string(),
+            Value::String(s) => s.trim().to_string(),
+            Value::Null => String::new(),
+            other => {
+                return Err(D::Error::custom(format!(
+                    "expected a number, got {}",
+                    other
+                )))
+            }
+        };
+        if text.is_empty() {
+            return Ok(T::default());
+        }
+
+        text.parse()
+            .map_err(|_| D::Error::custom(format!("invalid number {:?}", text)))
+    }
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -73,5 +142,7 @@ mod tests {
     fn test_parse_currency() {
         assert_eq!(parse_currency("$99.99").unwrap(), 99.99);
         assert_eq!(parse_currency("€1,234.56").unwrap(), 1234.56);
+        assert_eq!(parse_amount("€1,234.56").unwrap(), 1234.56);
+        assert!(parse_amount("free").is_err());
     }
 }
@@ -0,0 +1,323 @@
+use wasm_bindgen::prelude::*;
+use serde::{Deserialize, Serialize};
+use thiserror::Error;
+
+/// Years ahead of today a card can still be valid until
+const MAX_EXPIRY_YEARS: u32 = 20;
+
+/// Card networks accepted at checkout
+#[wasm_bindgen]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize)]
+pub enum CardBrand {
+    Visa,
+    Mastercard,
+    Amex,
+    Discover,
+    Jcb,
+    DinersClub,
+}
+
+impl CardBrand {
+    /// The brand issuing a card number, from its leading digits (IIN ranges)
+    pub fn detect(digits: &str) -> Option<CardBrand> {
+        let prefix = |len: usize| -> Option<u32> { digits.get(..len)?.parse().ok() };
+        let in_range = |len: usize, low: u32, high: u32| {
+            prefix(len).is_some_and(|p| (low..=high).contains(&p))
+        };
+
+        if digits.starts_with('4') {
+            Some(CardBrand::Visa)
+        } else if in_range(2, 51, 55) || in_range(4, 2221, 2720) {
+            Some(CardBrand::Mastercard)
+        } else if in_range(2, 34, 34) || in_range(2, 37, 37) {
+            Some(CardBrand::Amex)
+        } else if in_range(4, 6011, 6011)
+            || in_range(3, 644, 649)
+            || in_range(2, 65, 65)
+            || in_range(6, 622126, 622925)
+        {
+            Some(CardBrand::Discover)
+        } else if in_range(4, 3528, 3589) {
+            Some(CardBrand::Jcb)
+        } else if in_range(3, 300, 305)
+            || in_range(4, 3095, 3095)
+            || in_range(2, 36, 36)
+            || in_range(2, 38, 39)
+        {
+            Some(CardBrand::DinersClub)
+        } else {
+            None
+        }
+    }
+
+    /// Numbers of digits a card of this brand can have
+    pub fn lengths(&self) -> &'static [usize] {
+        match self {
+            CardBrand::Visa => &[13, 16, 19],
+            CardBrand::Mastercard => &[16],
+            CardBrand::Amex => &[15],
+            CardBrand::Discover | CardBrand::Jcb => &[16, 17, 18, 19],
+            CardBrand::DinersClub => &[14, 15, 16, 17, 18, 19],
+        }
+    }
+
+    /// Digits in the security code: 4 on the front of an Amex, 3 on the back of the others
+    pub fn cvv_length(&self) -> usize {
+        match self {
+            CardBrand::Amex => 4,
+            _ => 3,
+        }
+    }
+
+    /// How the number is grouped when printed on the card
+    fn groups(&self, length: usize) -> &'static [usize] {
+        match (self, length) {
+            (CardBrand::Amex, _) => &[4, 6, 5],
+            (CardBrand::DinersClub, 14) => &[4, 6, 4],
+            _ => &[4, 4, 4, 4, 4],
+        }
+    }
+}
+
+/// Why card details were rejected
+#[derive(Debug, Clone, PartialEq, Eq, Error)]
+pub enum CardError {
+    #[error("Card number may only contain digits, spaces and dashes")]
+    InvalidCharacters,
+    #[error("Card type is not accepted")]
+    UnknownBrand,
+    #[error("Card number has the wrong number of digits for {0:?}")]
+    InvalidLength(CardBrand),
+    #[error("Card number is not valid")]
+    Checksum,
+    #[error("Expiration month must be 1 to 12")]
+    InvalidMonth,
+    #[error("Expiration year is not valid")]
+    InvalidYear,
+    #[error("Card has expired")]
+    Expired,
+    #[error("Security code must be {0} digits")]
+    InvalidCvv(usize),
+    #[error("Security code must be 3 or 4 digits")]
+    InvalidCvvAnyBrand,
+}
+
+impl From<CardError> for JsValue {
+    fn from(error: CardError) -> Self {
+        JsValue::from_str(&error.to_string())
+    }
+}
+
+/// The digits of a card number, which may be written with spaces or dashes
+pub fn card_digits(number: &str) -> Result<String, CardError> {
+    let number = number.trim();
+    if number.is_empty()
+        || !number
+            .chars()
+            .all(|c| c.is_ascii_digit() || c == ' ' || c == '-')
+    {
+        return Err(CardError::InvalidCharacters);
+    }
+
+    Ok(number.chars().filter(char::is_ascii_digit).collect())
+}
+
+/// Check a card number's brand, length and Luhn checksum. Returns the brand.
+pub fn validate_card_number(number: &str) -> Result<CardBrand, CardError> {
+    let digits = card_digits(number)?;
+    let brand = CardBrand::detect(&digits).ok_or(CardError::UnknownBrand)?;
+    if !brand.lengths().contains(&digits.len()) {
+        return Err(CardError::InvalidLength(brand));
+    }
+    if !luhn(&digits) {
+        return Err(CardError::Checksum);
+    }
+
+    Ok(brand)
+}
+
+/// Luhn checksum over a string of digits
+pub fn luhn(digits: &str) -> bool {
+    let mut sum = 0;
+    let mut double = false;
+
+    for ch in digits.chars().rev() {
+        let Some(mut digit) = ch.to_digit(10) else {
+            return false;
+        };
+        if double {
+            digit *= 2;
+            if digit > 9 {
+                digit -= 9;
+            }
+        }
+        sum += digit;
+        double = !double;
+    }
+
+    sum % 10 == 0
+}
+
+/// Check an expiration date at `now` (seconds since the epoch). The card is
+/// valid through the end of its expiration month. Two-digit years are 20YY.
+pub fn validate_expiry(month: u32, year: u32, now: u64) -> Result<(), CardError> {
+    if !(1..=12).contains(&month) {
+        return Err(CardError::InvalidMonth);
+    }
+    let year = match year {
+        0..=99 => 2000 + year,
+        1000..=9999 => year,
+        _ => return Err(CardError::InvalidYear),
+    };
+
+    let (this_year, this_month) = year_month(now);
+    if (year, month) < (this_year, this_month) {
+        return Err(CardError::Expired);
+    }
+    if year > this_year + MAX_EXPIRY_YEARS {
+        return Err(CardError::InvalidYear);
+    }
+
+    Ok(())
+}
+
+/// Month and year of an expiration date written "MM/YY" or "MM/YYYY"
+pub fn parse_expiry(value: &str) -> Option<(u32, u32)> {
+    let (month, year) = value.trim().split_once('/')?;
+    let (month, year) = (month.trim(), year.trim());
+    let digits = |s: &str| !s.is_empty() && s.chars().all(|c| c.is_ascii_digit());
+    if !digits(month) || month.len() > 2 || !digits(year) || !matches!(year.len(), 2 | 4) {
+        return None;
+    }
+
+    Some((month.parse().ok()?, year.parse().ok()?))
+}
+
+/// Check a security code's length for the card's brand. Without a brand either
+/// length is accepted.
+pub fn validate_cvv(cvv: &str, brand: Option<CardBrand>) -> Result<(), CardError> {
+    let cvv = cvv.trim();
+    let valid = cvv.chars().all(|c| c.is_ascii_digit())
+        && match brand {
+            Some(brand) => cvv.len() == brand.cvv_length(),
+            None => (3..=4).contains(&cvv.len()),
+        };
+
+    match (valid, brand) {
+        (true, _) => Ok(()),
+        (false, Some(brand)) => Err(CardError::InvalidCvv(brand.cvv_length())),
+        (false, None) => Err(CardError::InvalidCvvAnyBrand),
+    }
+}
+
+/// A card number safe to show: all but the last four digits masked, grouped as
+/// on the card, e.g. "**** **** **** 0366". Anything that is not a card number
+/// is masked entirely.
+pub fn mask_card_number(number: &str) -> String {
+    let Ok(digits) = card_digits(number) else {
+        return "*".repeat(number.trim().chars().count());
+    };
+    let visible = digits.len().saturating_sub(4);
+    let masked: String = digits
+        .chars()
+        .enumerate()
+        .map(|(i, c)| if i < visible { '*' } else { c })
+        .collect();
+
+    let groups = CardBrand::detect(&digits)
+        .map(|brand| brand.groups(digits.len()))
+        .unwrap_or(&[4, 4, 4, 4, 4]);
+    let mut parts = vec![];
+    let mut rest = masked.as_str();
+    for size in groups {
+        if rest.len() <= *size {
+            break;
+        }
+        let (part, tail) = rest.split_at(*size);
+        parts.push(part);
+        rest = tail;
+    }
+    parts.push(rest);
+
+    parts.join(" ")
+}
+
+/// UTC year and month (1 to 12) of a time in seconds since the epoch
+fn year_month(epoch: u64) -> (u32, u32) {
+    // Civil from days, after Howard Hinnant's date algorithms
+    let days = (epoch / 86_400) as i64 + 719_468;
+    let era = days.div_euclid(146_097);
+    let day_of_era = days.rem_euclid(146_097);
+    let year_of_era =
+        (day_of_era - day_of_era / 1460 + day_of_era / 36_524 - day_of_era / 146_096) / 365;
+    let day_of_year = day_of_era - (365 * year_of_era + year_of_era / 4 - year_of_era / 100);
+    let mp = (5 * day_of_year + 2) / 153;
+    let month = if mp < 10 { mp + 3 } else { mp - 9 };
+    let year = year_of_era + era * 400 + i64::from(month <= 2);
+
+    (year as u32, month as u32)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_card_checks() {
+        let cards = [
+            ("4532 0151 1283 0366", CardBrand::Visa),
+            ("5555-5555-5555-4444", CardBrand::Mastercard),
+            ("2223003122003222", CardBrand::Mastercard),
+            ("378282246310005", CardBrand::Amex),
+            ("6011111111111117", CardBrand::Discover),
+            ("3530111333300000", CardBrand::Jcb),
+            ("30569309025904", CardBrand::DinersClub),
+        ];
+        for (number, brand) in cards {
+            assert_eq!(validate_card_number(number), Ok(brand), "{}", number);
+        }
+        assert_eq!(
+            validate_card_number("4532015112830367"),
+            Err(CardError::Checksum)
+        );
+        assert_eq!(
+            validate_card_number("37828224631000"),
+            Err(CardError::InvalidLength(CardBrand::Amex))
+        );
+        assert_eq!(
+            validate_card_number("9111111111111111"),
+            Err(CardError::UnknownBrand)
+        );
+        assert_eq!(
+            validate_card_number("4111-abcd"),
+            Err(CardError::InvalidCharacters)
+        );
+
+        // 2024-06-15
+        let now = 1_718_409_600;
+        assert_eq!(year_month(now), (2024, 6));
+        assert_eq!(validate_expiry(6, 2024, now), Ok(()));
+        assert_eq!(validate_expiry(12, 29, now), Ok(()));
+        assert_eq!(validate_expiry(5, 2024, now), Err(CardError::Expired));
+        assert_eq!(validate_expiry(13, 2030, now), Err(CardError::InvalidMonth));
+        assert_eq!(validate_expiry(1, 2099, now), Err(CardError::InvalidYear));
+        assert_eq!(parse_expiry("07/27"), Some((7, 27)));
+        assert_eq!(parse_expiry("7 / 2027"), Some((7, 2027)));
+        assert_eq!(parse_expiry("0727"), None);
+
+        assert_eq!(validate_cvv("1234", Some(CardBrand::Amex)), Ok(()));
+        assert_eq!(
+            validate_cvv("123", Some(CardBrand::Amex)),
+            Err(CardError::InvalidCvv(4))
+        );
+        assert_eq!(validate_cvv("123", Some(CardBrand::Visa)), Ok(()));
+        assert_eq!(
+            validate_cvv("12a", None),
+            Err(CardError::InvalidCvvAnyBrand)
+        );
+
+        assert_eq!(mask_card_number("4532015112830366"), "**** **** **** 0366");
+        assert_eq!(mask_card_number("3782 822463 10005"), "**** ****** *0005");
+        assert_eq!(mask_card_number("not a card"), "**********");
+    }
+}
@@ -0,0 +1,170 @@
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+
+use super::{PatternAnchor, ValidationRule, ValidationType};
+use crate::utils::flag;
+
+/// One entry of a form schema. Entries without an `id` (legends, hints, submit
+/// buttons in the legacy `*_validation.json` files) are layout only.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct FormField {
+    pub id: String,
+    #[serde(rename = "type", default)]
+    pub field_type: String,
+    #[serde(default)]
+    pub label: String,
+    #[serde(default, with = "flag")]
+    pub required: bool,
+    /// Fields marked `ignore` are neither shown nor validated
+    #[serde(default, with = "flag")]
+    pub ignore: bool,
+    /// Regex the whole value has to match, like the HTML `pattern` attribute
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub pattern: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub minlength: Option<usize>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub maxlength: Option<usize>,
+    /// Further rules, checked after the ones implied by the attributes above
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub rules: Vec<ValidationRule>,
+}
+
+impl FormField {
+    /// The name used in error messages
+    pub fn name(&self) -> &str {
+        if self.label.is_empty() {
+            &self.id
+        } else {
+            &self.label
+        }
+    }
+
+    /// Every rule a non-empty value is checked against: the ones implied by the
+    /// field's type and attributes, then its own
+    pub fn value_rules(&self) -> Vec<ValidationRule> {
+        let name = self.name();
+        let rule = |rule_type, param: Option<String>, message: String| ValidationRule {
+            rule_type,
+            param,
+            flags: String::new(),
+            anchor: PatternAnchor::Full,
+            when: None,
+            unless: None,
+            message,
+        };
+
+        let mut rules = vec![];
+        match self.field_type.as_str() {
+            "email" => rules.push(rule(
+                ValidationType::Email,
+                None,
+                format!("{} must be a valid email address", name),
+            )),
+            "tel" | "phone" => rules.push(rule(
+                ValidationType::Phone,
+                None,
+                format!("{} must be a valid phone number", name),
+            )),
+            _ => {}
+        }
+        if let Some(min) = self.minlength {
+            rules.push(rule(
+                ValidationType::MinLength,
+                Some(min.to_string()),
+                format!("{} must be at least {} characters", name, min),
+            ));
+        }
+        if let Some(max) = self.maxlength {
+            rules.push(rule(
+                ValidationType::MaxLength,
+                Some(max.to_string()),
+                format!("{} must be at most {} characters", name, max),
+            ));
+        }
+        if let Some(pattern) = &self.pattern {
+            rules.push(rule(
+                ValidationType::Pattern,
+                Some(pattern.clone()),
+                format!("{} is not in the expected format", name),
+            ));
+        }
+        rules.extend(self.rules.iter().cloned());
+
+        rules
+    }
+}
+
+/// The fields of a form and how to validate them. Deserializes from a legacy
+/// schema array (`legacy/platform/appaccountcreate_validation.json`) or from
+/// `{ "fields": [...] }`.
+#[derive(Debug, Clone, Default, PartialEq, Serialize)]
+pub struct FormSchema {
+    pub fields: Vec<FormField>,
+}
+
+impl FormSchema {
+    pub fn from_json(json: &str) -> Result<FormSchema, String> {
+        serde_json::from_str(json).map_err(|e| format!("Failed to parse form schema: {}", e))
+    }
+
+    /// Fields that are validated, in schema order
+    pub fn active_fields(&self) -> impl Iterator<Item = &FormField> {
+        self.fields.iter().filter(|field| !field.ignore)
+    }
+}
+
+impl<'de> Deserialize<'de> for FormSchema {
+    fn deserialize<D: serde::Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        #[derive(Deserialize)]
+        #[serde(untagged)]
+        enum Layout {
+            Legacy(Vec<Value>),
+            Fields { fields: Vec<Value> },
+        }
+
+        let entries = match Layout::deserialize(deserializer)? {
+            Layout::Legacy(entries) | Layout::Fields { fields: entries } => entries,
+        };
+        let fields = entries
+            .into_iter()
+            .filter(|entry| entry.get("id").is_some())
+            .map(serde_json::from_value)
+            .collect::<Result<Vec<FormField>, _>>()
+            .map_err(serde::de::Error::custom)?;
+
+        Ok(FormSchema { fields })
+    }
+}
+
+/// A form value as a string. Numbers and booleans are converted; anything else
+/// counts as empty.
+pub fn field_value(data: &Value, id: &str) -> String {
+    match data.get(id) {
+        Some(Value::String(s)) => s.clone(),
+        Some(Value::Number(n)) => n.to_string(),
+        Some(Value::Bool(b)) => u8::from(*b).to_string(),
+        _ => String::new(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_load_legacy_schema() {
+        let json = include_str!("../../../legacy/platform/appaccountcreate_validation.json");
+        let schema = FormSchema::from_json(json).unwrap();
+
+        // Legend, hint and submit entries are dropped
+        assert_eq!(schema.fields.len(), 18);
+        let email = &schema.fields[1];
+        assert_eq!((email.id.as_str(), email.required), ("email", true));
+        assert!(!schema.fields[0].required);
+        assert_eq!(schema.active_fields().count(), 14);
+
+        let wrapped = format!("{{ \"fields\": {} }}", json);
+        assert_eq!(FormSchema::from_json(&wrapped).unwrap(), schema);
+    }
+}
@@ -1,54 +1,238 @@
 use wasm_bindgen::prelude::*;
 use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use std::cell::RefCell;
+
+use crate::utils::epoch_now;
+
+mod card;
+mod form;
+mod pattern;
+mod phone;
+mod postal;
+
+pub use card::{
+    card_digits, luhn, mask_card_number, parse_expiry, validate_card_number, validate_cvv,
+    validate_expiry, CardBrand, CardError,
+};
+pub use form::{field_value, FormField, FormSchema};
+pub use pattern::{Pattern, PatternAnchor, PatternCache, PatternError};
+pub use phone::{parse_phone, PhoneLineType, PhoneNumber};
+pub use postal::normalize_postal_code;
 
 #[wasm_bindgen]
 #[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
 pub enum ValidationType {
     Required,
     Email,
-    Phone,
-    ZipCode,
-    CreditCard,
+    Phone,      // Phone number as dialled in the country in `param`, or with "+"; US if unset
+    ZipCode,    // Postal code for the country in `param` (ISO alpha-2), US if unset
+    CreditCard, // Card number of an accepted brand, with the brand's length and a valid checksum
+    CardExpiry, // "MM/YY" or "MM/YYYY", not before the current month
+    CardCvv,    // Security code for the brand of the card number in the field named by `param`
     MinLength,
     MaxLength,
     Pattern,
+    EqualsField, // Same value as the field named by `param`, e.g. a password confirmation
+}
+
+/// A test on another field of the same form
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Condition {
+    pub field: String,
+    /// The value the field must have. Without one the field must be set:
+    /// not empty and not "0", as checkbox flags like `bill_to_ship` are sent.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub equals: Option<String>,
+}
+
+impl Condition {
+    pub fn holds(&self, form_data: &Value) -> bool {
+        let value = field_value(form_data, &self.field);
+        match &self.equals {
+            Some(expected) => value.trim() == expected,
+            None => !matches!(value.trim(), "" | "0"),
+        }
+    }
 }
 
-#[derive(Debug, Clone, Serialize, Deserialize)]
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
 pub struct ValidationRule {
     pub rule_type: ValidationType,
     #[serde(skip_serializing_if = "Option::is_none")]
     pub param: Option<String>,
+    /// Regex flags for `Pattern` rules, e.g. "i"
+    #[serde(default, skip_serializing_if = "String::is_empty")]
+    pub flags: String,
+    /// How much of the value a `Pattern` rule has to match
+    #[serde(default)]
+    pub anchor: PatternAnchor,
+    /// Only check the rule when this holds, e.g. `recovery_answer` is required
+    /// when `create_customer` is set
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub when: Option<Condition>,
+    /// Skip the rule when this holds, e.g. the ship address is required unless
+    /// `bill_to_ship` is set
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub unless: Option<Condition>,
     pub message: String,
 }
 
-#[derive(Debug, Clone, Serialize, Deserialize)]
+impl ValidationRule {
+    /// Whether the rule's conditions let it apply to this form
+    pub fn applies(&self, form_data: &Value) -> bool {
+        self.when.as_ref().is_none_or(|c| c.holds(form_data))
+            && !self.unless.as_ref().is_some_and(|c| c.holds(form_data))
+    }
+
+    /// Whether the rule is checked when the value is empty. Other rules only
+    /// check values that were filled in.
+    fn checks_empty(&self) -> bool {
+        matches!(
+            self.rule_type,
+            ValidationType::Required | ValidationType::EqualsField
+        )
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
 pub struct ValidationError {
     pub field: String,
     pub message: String,
 }
 
 #[wasm_bindgen]
-pub struct Validator;
+#[derive(Default)]
+pub struct Validator {
+    patterns: RefCell<PatternCache>,
+}
 
 #[wasm_bindgen]
 impl Validator {
     #[wasm_bindgen(constructor)]
     pub fn new() -> Validator {
-        Validator
+        Validator::default()
     }
 
-    /// Validate a single field value
+    /// Validate a single field value. Fails if a pattern rule's regex is invalid.
     pub fn validate_field(&self, value: &str, rule: JsValue) -> Result<bool, JsValue> {
         let rule: ValidationRule = serde_wasm_bindgen::from_value(rule)
             .map_err(|e| JsValue::from_str(&format!("Failed to parse rule: {}", e)))?;
 
+        Ok(self.check(value, &rule)?)
+    }
+
+    /// Validate a whole form (field ID -> value) against a schema: a legacy
+    /// `*_validation.json` array or `{ fields: [...] }`. Returns every
+    /// `ValidationError`, in schema order.
+    pub fn validate_form(&self, form_data: JsValue, schema: JsValue) -> Result<JsValue, JsValue> {
+        let form_data: Value = serde_wasm_bindgen::from_value(form_data)
+            .map_err(|e| JsValue::from_str(&format!("Failed to parse form data: {}", e)))?;
+        let schema: FormSchema = serde_wasm_bindgen::from_value(schema)
+            .map_err(|e| JsValue::from_str(&format!("Failed to parse form schema: {}", e)))?;
+
+        let errors = self.form_errors(&form_data, &schema)?;
+
+        errors
+            .serialize(&serde_wasm_bindgen::Serializer::json_compatible())
+            .map_err(|e| JsValue::from_str(&format!("Failed to serialize errors: {}", e)))
+    }
+
+    /// A postal code in its usual written form for a country, e.g.
+    /// `("k1a0b1", "CA")` -> "K1A 0B1". `undefined` if it is not valid there.
+    pub fn format_postal_code(&self, value: &str, country: &str) -> Option<String> {
+        normalize_postal_code(country, value)
+    }
+
+    /// Parse a phone number dialled in `country` or written with "+". Returns a
+    /// `PhoneNumber` (E.164, display form, line type), or `null` if it is not valid.
+    pub fn parse_phone(&self, value: &str, country: &str) -> Result<JsValue, JsValue> {
+        parse_phone(value, country)
+            .serialize(&serde_wasm_bindgen::Serializer::json_compatible())
+            .map_err(|e| JsValue::from_str(&format!("Failed to serialize phone number: {}", e)))
+    }
+
+    /// The brand of a card number from its leading digits, `undefined` if it is
+    /// not an accepted brand. Works on partial numbers, to show the card logo
+    /// while typing.
+    pub fn card_brand(&self, number: &str) -> Option<CardBrand> {
+        CardBrand::detect(&card_digits(number).ok()?)
+    }
+
+    /// Check a card's number, expiration and security code at checkout. Returns
+    /// the brand, or fails with the first problem found.
+    pub fn validate_card(
+        &self,
+        number: &str,
+        exp_month: u32,
+        exp_year: u32,
+        cvv: &str,
+    ) -> Result<CardBrand, JsValue> {
+        let brand = validate_card_number(number)?;
+        validate_expiry(exp_month, exp_year, epoch_now())?;
+        validate_cvv(cvv, Some(brand))?;
+
+        Ok(brand)
+    }
+
+    /// A card number for display with all but the last four digits masked,
+    /// e.g. "**** **** **** 0366"
+    pub fn mask_card_number(&self, number: &str) -> String {
+        mask_card_number(number)
+    }
+
+    /// Match a value against a regex, e.g. `matches_pattern(zip, "[0-9]{5}", "", PatternAnchor.Full)`
+    pub fn matches_pattern(
+        &self,
+        value: &str,
+        pattern: &str,
+        flags: &str,
+        anchor: PatternAnchor,
+    ) -> Result<bool, JsValue> {
+        Ok(self.is_match(value, pattern, flags, anchor)?)
+    }
+}
+
+impl Validator {
+    /// Whether a value passes a rule. Fields the rule refers to are taken as
+    /// empty; use `check_in_form` to compare against the rest of a form.
+    pub fn check(&self, value: &str, rule: &ValidationRule) -> Result<bool, PatternError> {
+        self.check_in_form(value, rule, &Value::Null)
+    }
+
+    /// Whether a value passes a rule, with the rest of the form for rules that
+    /// refer to other fields. A rule whose conditions do not hold passes.
+    pub fn check_in_form(
+        &self,
+        value: &str,
+        rule: &ValidationRule,
+        form_data: &Value,
+    ) -> Result<bool, PatternError> {
+        if !rule.applies(form_data) {
+            return Ok(true);
+        }
+
         let is_valid = match rule.rule_type {
             ValidationType::Required => !value.trim().is_empty(),
             ValidationType::Email => self.validate_email(value),
-            ValidationType::Phone => self.validate_phone(value),
-            ValidationType::ZipCode => self.validate_zipcode(value),
+            ValidationType::Phone => {
+                let country = rule.param.as_deref().unwrap_or("US");
+                parse_phone(value, country).is_some()
+            }
+            ValidationType::ZipCode => {
+                let country = rule.param.as_deref().unwrap_or("US");
+                normalize_postal_code(country, value).is_some()
+            }
             ValidationType::CreditCard => self.validate_credit_card(value),
+            ValidationType::CardExpiry => parse_expiry(value)
+                .is_some_and(|(month, year)| validate_expiry(month, year, epoch_now()).is_ok()),
+            ValidationType::CardCvv => {
+                let brand = rule.param.as_deref().and_then(|field| {
+                    let digits = card_digits(&field_value(form_data, field)).ok()?;
+                    CardBrand::detect(&digits)
+                });
+                validate_cvv(value, brand).is_ok()
+            }
             ValidationType::MinLength => {
                 if let Some(param) = &rule.param {
                     if let Ok(min) = param.parse::<usize>() {
@@ -72,66 +256,76 @@ impl Validator {
                 }
             }
             ValidationType::Pattern => {
-                if let Some(pattern) = &rule.param {
-                    // Simple pattern matching (would use regex in production)
-                    value.contains(pattern)
-                } else {
-                    false
-                }
+                let pattern = rule.param.as_deref().ok_or(PatternError::Missing)?;
+                self.is_match(value, pattern, &rule.flags, rule.anchor)?
             }
+            ValidationType::EqualsField => match &rule.param {
+                Some(other) => value == field_value(form_data, other),
+                None => false,
+            },
         };
 
         Ok(is_valid)
     }
 
-    /// Validate email format
-    fn validate_email(&self, email: &str) -> bool {
-        // Simple email validation (production would use regex)
-        email.contains('@') && email.contains('.') && email.len() >= 5
-    }
+    /// Every error in a form. A filled-in field fails once for each rule it
+    /// breaks. An empty one only fails if it is required, by the schema or by a
+    /// `Required` rule whose conditions hold, or if it should equal another field.
+    /// Rules are only checked when their conditions hold.
+    pub fn form_errors(
+        &self,
+        form_data: &Value,
+        schema: &FormSchema,
+    ) -> Result<Vec<ValidationError>, PatternError> {
+        let mut errors = vec![];
 
-    /// Validate phone number
-    fn validate_phone(&self, phone: &str) -> bool {
-        // Remove common phone number characters
-        let digits: String = phone.chars().filter(|c| c.is_ascii_digit()).collect();
-        digits.len() >= 10
-    }
+        for field in schema.active_fields() {
+            let value = field_value(form_data, &field.id);
+            let error = |message: String| ValidationError {
+                field: field.id.clone(),
+                message,
+            };
+
+            let empty = value.trim().is_empty();
+            if empty && field.required {
+                errors.push(error(format!("{} is required", field.name())));
+                continue;
+            }
+            for rule in field.value_rules() {
+                if empty && !rule.checks_empty() {
+                    continue;
+                }
+                if !self.check_in_form(&value, &rule, form_data)? {
+                    errors.push(error(rule.message));
+                }
+            }
+        }
 
-    /// Validate US ZIP code
-    fn validate_zipcode(&self, zip: &str) -> bool {
-        let digits: String = zip.chars().filter(|c| c.is_ascii_digit()).collect();
-        digits.len() == 5 || digits.len() == 9
+        Ok(errors)
     }
 
-    /// Validate credit card using Luhn algorithm
-    fn validate_credit_card(&self, card: &str) -> bool {
-        let digits: String = card.chars().filter(|c| c.is_ascii_digit()).collect();
+    /// Match a value against a regex, compiling it on first use
+    pub fn is_match(
+        &self,
+        value: &str,
+        pattern: &str,
+        flags: &str,
+        anchor: PatternAnchor,
+    ) -> Result<bool, PatternError> {
+        let pattern = self.patterns.borrow_mut().get(pattern, flags, anchor)?;
 
-        if digits.len() < 13 || digits.len() > 19 {
-            return false;
-        }
+        Ok(pattern.is_match(value))
+    }
 
-        // Luhn algorithm
-        let mut sum = 0;
-        let mut double = false;
-
-        for ch in digits.chars().rev() {
-            if let Some(digit) = ch.to_digit(10) {
-                let mut digit = digit;
-                if double {
-                    digit *= 2;
-                    if digit > 9 {
-                        digit -= 9;
-                    }
-                }
-                sum += digit;
-                double = !double;
-            } else {
-                return false;
-            }
-        }
+    /// Validate email format
+    fn validate_email(&self, email: &str) -> bool {
+        // Simple email validation (production would use regex)
+        email.contains('@') && email.contains('.') && email.len() >= 5
+    }
 
-        sum % 10 == 0
+    /// Validate a credit card number: brand, length and Luhn checksum
+    fn validate_credit_card(&self, card: &str) -> bool {
+        validate_card_number(card).is_ok()
     }
 }
 
@@ -153,5 +347,126 @@ mod tests {
         assert!(validator.validate_credit_card("4532015112830366"));
         // Invalid
         assert!(!validator.validate_credit_card("4532015112830367"));
+        // Passes Luhn, but no brand issues 13 digit cards starting with 5
+        assert!(!validator.validate_credit_card("5555555555554"));
+
+        let cvv = ValidationRule {
+            rule_type: ValidationType::CardCvv,
+            param: Some("CC".to_string()),
+            flags: String::new(),
+            anchor: PatternAnchor::default(),
+            when: None,
+            unless: None,
+            message: "Invalid security code".to_string(),
+        };
+        let amex = serde_json::json!({ "CC": "3782 822463 10005" });
+        assert_eq!(validator.check_in_form("1234", &cvv, &amex), Ok(true));
+        assert_eq!(validator.check_in_form("123", &cvv, &amex), Ok(false));
+        assert_eq!(validator.check("123", &cvv), Ok(true));
+    }
+
+    #[test]
+    fn test_pattern_rule() {
+        let validator = Validator::new();
+        let mut rule = ValidationRule {
+            rule_type: ValidationType::Pattern,
+            param: Some("[a-z]+-\\d+".to_string()),
+            flags: "i".to_string(),
+            anchor: PatternAnchor::Full,
+            when: None,
+            unless: None,
+            message: "Invalid code".to_string(),
+        };
+        assert_eq!(validator.check("ABC-12", &rule), Ok(true));
+        assert_eq!(validator.check("ABC-12x", &rule), Ok(false));
+
+        rule.param = Some("[a-z".to_string());
+        assert!(matches!(
+            validator.check("abc", &rule),
+            Err(PatternError::Invalid { .. })
+        ));
+        rule.param = None;
+        assert_eq!(validator.check("abc", &rule), Err(PatternError::Missing));
+    }
+
+    #[test]
+    fn test_validate_form() {
+        let validator = Validator::new();
+        let schema: FormSchema = serde_json::from_value(serde_json::json!([
+            { "type": "legend", "content": "Sign up" },
+            { "id": "email", "type": "email", "label": "Email", "required": 1 },
+            { "id": "company", "type": "text", "label": "Company", "minlength": 3, "pattern": "[A-Za-z ]+" },
+            { "id": "code", "type": "text", "required": 1, "ignore": 1 }
+        ]))
+        .unwrap();
+
+        let errors = validator
+            .form_errors(&serde_json::json!({ "company": "A1" }), &schema)
+            .unwrap();
+        let fields: Vec<(&str, &str)> = errors
+            .iter()
+            .map(|e| (e.field.as_str(), e.message.as_str()))
+            .collect();
+        assert_eq!(
+            fields,
+            vec![
+                ("email", "Email is required"),
+                ("company", "Company must be at least 3 characters"),
+                ("company", "Company is not in the expected format"),
+            ]
+        );
+
+        let valid = serde_json::json!({ "email": "a@example.com", "company": "Acme" });
+        assert_eq!(validator.form_errors(&valid, &schema).unwrap(), vec![]);
+    }
+
+    #[test]
+    fn test_cross_field_rules() {
+        let validator = Validator::new();
+        let schema: FormSchema = serde_json::from_value(serde_json::json!([
+            { "id": "new_password", "label": "Password" },
+            { "id": "password_confirm", "rules": [
+                { "rule_type": "EqualsField", "param": "new_password", "message": "Passwords do not match" }
+            ] },
+            { "id": "ship_address1", "rules": [
+                { "rule_type": "Required", "unless": { "field": "bill_to_ship", "equals": "1" },
+                  "message": "Ship address is required" }
+            ] },
+            { "id": "recovery_answer", "rules": [
+                { "rule_type": "Required", "when": { "field": "create_customer" },
+                  "message": "Recovery answer is required" }
+            ] }
+        ]))
+        .unwrap();
+        let messages = |form: serde_json::Value| -> Vec<String> {
+            validator
+                .form_errors(&form, &schema)
+                .unwrap()
+                .into_iter()
+                .map(|e| e.message)
+                .collect()
+        };
+
+        // The `want` of legacy/examples/sample-data/order.json
+        let want = serde_json::json!({
+            "recovery_answer": "butch", "bill_to_ship": "1", "recovery_hint": "2",
+            "new_password": "password", "password_confirm": "password", "create_customer": "1"
+        });
+        assert!(messages(want).is_empty());
+
+        let form = serde_json::json!({
+            "new_password": "password", "bill_to_ship": "0", "create_customer": "1"
+        });
+        assert_eq!(
+            messages(form),
+            vec![
+                "Passwords do not match",
+                "Ship address is required",
+                "Recovery answer is required"
+            ]
+        );
+        assert!(
+            messages(serde_json::json!({ "bill_to_ship": "1", "create_customer": "0" })).is_empty()
+        );
     }
 }
@@ -0,0 +1,156 @@
+use wasm_bindgen::prelude::*;
+use regex::{Regex, RegexBuilder};
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::rc::Rc;
+use thiserror::Error;
+
+/// Compiled patterns kept by a validator before the cache is emptied
+const MAX_CACHED_PATTERNS: usize = 256;
+
+/// How much of the value a pattern has to match
+#[wasm_bindgen]
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Hash, Serialize, Deserialize)]
+pub enum PatternAnchor {
+    #[default]
+    Search, // Anywhere in the value
+    Start, // From the start of the value
+    End,   // Up to the end of the value
+    Full,  // The whole value, like the HTML `pattern` attribute
+}
+
+/// Why a pattern rule could not be checked
+#[derive(Debug, Clone, PartialEq, Eq, Error)]
+pub enum PatternError {
+    #[error("Pattern rule has no pattern")]
+    Missing,
+    #[error("Unknown pattern flag '{0}'")]
+    UnknownFlag(char),
+    #[error("Invalid pattern {pattern}: {message}")]
+    Invalid { pattern: String, message: String },
+}
+
+impl From<PatternError> for JsValue {
+    fn from(error: PatternError) -> Self {
+        JsValue::from_str(&error.to_string())
+    }
+}
+
+/// A compiled regular expression
+#[derive(Debug, Clone)]
+pub struct Pattern {
+    regex: Regex,
+}
+
+impl Pattern {
+    /// Compile `source` with JS style `flags`: `i` ignores case, `m` makes `^`/`$`
+    /// match at line breaks, `s` lets `.` match line breaks, `x` ignores whitespace.
+    /// `u` and `g` are accepted and have no effect.
+    pub fn new(source: &str, flags: &str, anchor: PatternAnchor) -> Result<Pattern, PatternError> {
+        let mut builder = match anchor {
+            PatternAnchor::Search => RegexBuilder::new(source),
+            PatternAnchor::Start => RegexBuilder::new(&format!(r"\A(?:{})", source)),
+            PatternAnchor::End => RegexBuilder::new(&format!(r"(?:{})\z", source)),
+            PatternAnchor::Full => RegexBuilder::new(&format!(r"\A(?:{})\z", source)),
+        };
+        for flag in flags.chars() {
+            match flag {
+                'i' => builder.case_insensitive(true),
+                'm' => builder.multi_line(true),
+                's' => builder.dot_matches_new_line(true),
+                'x' => builder.ignore_whitespace(true),
+                'u' | 'g' => &mut builder,
+                other => return Err(PatternError::UnknownFlag(other)),
+            };
+        }
+
+        let regex = builder.build().map_err(|e| PatternError::Invalid {
+            pattern: source.to_string(),
+            message: e.to_string(),
+        })?;
+
+        Ok(Pattern { regex })
+    }
+
+    pub fn is_match(&self, value: &str) -> bool {
+        self.regex.is_match(value)
+    }
+}
+
+/// Compiled patterns by source, flags and anchoring, so each rule is only
+/// compiled once
+#[derive(Debug, Default)]
+pub struct PatternCache {
+    patterns: HashMap<(String, String, PatternAnchor), Rc<Pattern>>,
+}
+
+impl PatternCache {
+    pub fn get(
+        &mut self,
+        source: &str,
+        flags: &str,
+        anchor: PatternAnchor,
+    ) -> Result<Rc<Pattern>, PatternError> {
+        let key = (source.to_string(), flags.to_string(), anchor);
+        if let Some(pattern) = self.patterns.get(&key) {
+            return Ok(pattern.clone());
+        }
+
+        let pattern = Rc::new(Pattern::new(source, flags, anchor)?);
+        if self.patterns.len() >= MAX_CACHED_PATTERNS {
+            self.patterns.clear();
+        }
+        self.patterns.insert(key, pattern.clone());
+
+        Ok(pattern)
+    }
+
+    pub fn len(&self) -> usize {
+        self.patterns.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.patterns.is_empty()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_anchors_flags_and_cache() {
+        let full = Pattern::new(r"[a-z]{3}\d", "", PatternAnchor::Full).unwrap();
+        assert!(full.is_match("abc1"));
+        assert!(!full.is_match("xabc1"));
+        assert!(!full.is_match("ABC1"));
+
+        let search = Pattern::new(r"[a-z]{3}\d", "i", PatternAnchor::Search).unwrap();
+        assert!(search.is_match("xx ABC1 yy"));
+        assert!(Pattern::new("a|b", "", PatternAnchor::Full)
+            .unwrap()
+            .is_match("b"));
+        assert!(Pattern::new("^a", "", PatternAnchor::End)
+            .unwrap()
+            .is_match("a"));
+        assert!(!Pattern::new("b", "", PatternAnchor::Start)
+            .unwrap()
+            .is_match("ab"));
+
+        assert_eq!(
+            Pattern::new("a", "q", PatternAnchor::Search).unwrap_err(),
+            PatternError::UnknownFlag('q')
+        );
+        assert!(matches!(
+            Pattern::new("(unclosed", "", PatternAnchor::Search),
+            Err(PatternError::Invalid { .. })
+        ));
+
+        let mut cache = PatternCache::default();
+        let first = cache.get(r"\d+", "", PatternAnchor::Full).unwrap();
+        let again = cache.get(r"\d+", "", PatternAnchor::Full).unwrap();
+        assert!(Rc::ptr_eq(&first, &again));
+        cache.get(r"\d+", "i", PatternAnchor::Full).unwrap();
+        assert_eq!(cache.len(), 2);
+    }
+}
@@ -0,0 +1,493 @@
+use wasm_bindgen::prelude::*;
+use serde::{Deserialize, Serialize};
+
+/// Country calling codes for the countries with numbering plans below
+const CALLING_CODES: [(&str, &str); 9] = [
+    ("US", "1"),
+    ("CA", "1"),
+    ("GB", "44"),
+    ("DE", "49"),
+    ("FR", "33"),
+    ("AU", "61"),
+    ("JP", "81"),
+    ("NL", "31"),
+    ("BR", "55"),
+];
+
+/// What kind of line a number belongs to
+#[wasm_bindgen]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub enum PhoneLineType {
+    Mobile,
+    Landline,
+    TollFree,
+    Unknown, // The numbering plan does not tell (e.g. US and CA), or the country is not known
+}
+
+/// A valid phone number
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct PhoneNumber {
+    /// ISO alpha-2 country, empty for a calling code without a known numbering plan
+    pub country: String,
+    /// "+" and up to 15 digits, e.g. "+442079460958". `None` for a number dialled
+    /// in a country without a known numbering plan, whose calling code is not known.
+    pub e164: Option<String>,
+    /// As written in the country, e.g. "020 7946 0958"
+    pub display: String,
+    pub line_type: PhoneLineType,
+}
+
+/// Parse a phone number written as it would be dialled in `default_country`, or
+/// internationally with "+" or "00". Returns `None` for anything that is not a
+/// valid number: letters, too few or too many digits, or impossible prefixes.
+///
+/// Countries without a numbering plan below accept 7 to 15 digits, written with
+/// the usual separators.
+pub fn parse_phone(number: &str, default_country: &str) -> Option<PhoneNumber> {
+    let trimmed = number.trim();
+    let (international, rest) = match trimmed.strip_prefix('+') {
+        Some(rest) => (true, rest),
+        None => (false, trimmed),
+    };
+    if !rest
+        .chars()
+        .all(|c| c.is_ascii_digit() || matches!(c, ' ' | '-' | '.' | '(' | ')' | '/'))
+    {
+        return None;
+    }
+    let digits: String = rest.chars().filter(char::is_ascii_digit).collect();
+
+    if international {
+        return international_number(&digits, default_country);
+    }
+    if let Some(digits) = digits.strip_prefix("00") {
+        return international_number(digits, default_country);
+    }
+
+    let country = default_country.trim().to_ascii_uppercase();
+    if !CALLING_CODES.iter().any(|(c, _)| *c == country) {
+        return (7..=15).contains(&digits.len()).then(|| PhoneNumber {
+            country,
+            e164: None,
+            display: trimmed.to_string(),
+            line_type: PhoneLineType::Unknown,
+        });
+    }
+    let national = match country.as_str() {
+        // NANP numbers may be dialled with the leading 1
+        "US" | "CA" if digits.len() == 11 => digits.strip_prefix('1')?,
+        "US" | "CA" => &digits,
+        // Brazil: a carrier code can follow the 0 for long distance calls
+        "BR" if digits.len() >= 13 => digits.strip_prefix('0')?.get(2..)?,
+        _ => digits.strip_prefix('0').unwrap_or(&digits),
+    };
+
+    national_number(&country, national)
+}
+
+/// `digits` follow the "+": a calling code, then the national number
+fn international_number(digits: &str, default_country: &str) -> Option<PhoneNumber> {
+    let default_country = default_country.trim().to_ascii_uppercase();
+
+    // The default country first, so +1 stays CA for Canadian forms
+    let plan = CALLING_CODES
+        .iter()
+        .filter(|(country, _)| *country == default_country)
+        .chain(CALLING_CODES.iter())
+        .find(|(_, code)| digits.starts_with(code));
+
+    match plan {
+        Some((country, code)) => {
+            // "+44 (0)20 ..." keeps the trunk 0 that is not dialled from abroad
+            let national = &digits[code.len()..];
+            let national = match *code {
+                "1" => national,
+                _ => national.strip_prefix('0').unwrap_or(national),
+            };
+            national_number(country, national)
+        }
+        None if (7..=15).contains(&digits.len()) && !digits.starts_with('0') => {
+            let e164 = format!("+{}", digits);
+            Some(PhoneNumber {
+                country: String::new(),
+                display: e164.clone(),
+                e164: Some(e164),
+                line_type: PhoneLineType::Unknown,
+            })
+        }
+        None => None,
+    }
+}
+
+/// Check a national significant number (no trunk prefix) against a country's plan
+fn national_number(country: &str, nsn: &str) -> Option<PhoneNumber> {
+    let (_, code) = CALLING_CODES.iter().find(|(c, _)| *c == country)?;
+    if nsn.is_empty() || !nsn.chars().all(|c| c.is_ascii_digit()) {
+        return None;
+    }
+    let (line_type, display) = match country {
+        "US" | "CA" => nanp(nsn)?,
+        "GB" => british(nsn)?,
+        "DE" => german(nsn)?,
+        "FR" => french(nsn)?,
+        "AU" => australian(nsn)?,
+        "JP" => japanese(nsn)?,
+        "NL" => dutch(nsn)?,
+        "BR" => brazilian(nsn)?,
+        _ => return None,
+    };
+
+    Some(PhoneNumber {
+        country: country.to_string(),
+        e164: Some(format!("+{}{}", code, nsn)),
+        display,
+        line_type,
+    })
+}
+
+/// Split `digits` into groups of the given sizes, the last one taking the rest
+fn group(digits: &str, sizes: &[usize], separator: &str) -> String {
+    let mut parts = vec![];
+    let mut rest = digits;
+    for size in sizes {
+        if rest.len() <= *size {
+            break;
+        }
+        let (part, tail) = rest.split_at(*size);
+        parts.push(part);
+        rest = tail;
+    }
+    parts.push(rest);
+
+    parts.join(separator)
+}
+
+/// NPA-NXX-XXXX. Mobile and landline numbers share area codes.
+fn nanp(nsn: &str) -> Option<(PhoneLineType, String)> {
+    let bytes = nsn.as_bytes();
+    if nsn.len() != 10 || bytes[0] < b'2' || bytes[3] < b'2' {
+        return None;
+    }
+    let line_type = match &nsn[..3] {
+        "800" | "833" | "844" | "855" | "866" | "877" | "888" => PhoneLineType::TollFree,
+        _ => PhoneLineType::Unknown,
+    };
+
+    Some((
+        line_type,
+        format!("({}) {}-{}", &nsn[..3], &nsn[3..6], &nsn[6..]),
+    ))
+}
+
+fn british(nsn: &str) -> Option<(PhoneLineType, String)> {
+    let national = format!("0{}", nsn);
+    match (nsn.len(), nsn.as_bytes()[0]) {
+        (10, b'7') if !nsn.starts_with("70") && !nsn.starts_with("76") => {
+            Some((PhoneLineType::Mobile, group(&national, &[5], " ")))
+        }
+        (10, b'2') => Some((PhoneLineType::Landline, group(&national, &[3, 4], " "))),
+        (9 | 10, b'1') => Some((PhoneLineType::Landline, group(&national, &[5], " "))),
+        (9 | 10, b'8') if nsn.starts_with("80") => {
+            Some((PhoneLineType::TollFree, group(&national, &[4, 3], " ")))
+        }
+        _ => None,
+    }
+}
+
+/// Area codes vary in length, so landlines are only checked for length. The
+/// area code is split off at its usual length: 2 digits for Berlin, Hamburg,
+/// Frankfurt and Munich, 3 for the other large cities (x1 and 20x codes), else 4.
+fn german(nsn: &str) -> Option<(PhoneLineType, String)> {
+    let national = format!("0{}", nsn);
+    if nsn.starts_with("15") || nsn.starts_with("16") || nsn.starts_with("17") {
+        return (10..=11)
+            .contains(&nsn.len())
+            .then(|| (PhoneLineType::Mobile, group(&national, &[4], " ")));
+    }
+    if nsn.starts_with("800") {
+        return (nsn.len() == 10).then(|| (PhoneLineType::TollFree, group(&national, &[4], " ")));
+    }
+
+    if !nsn.starts_with(['2', '3', '4', '5', '6', '7', '8', '9']) || !(6..=11).contains(&nsn.len())
+    {
+        return None;
+    }
+    let area = match nsn.as_bytes() {
+        [b'3', b'0', ..] | [b'4', b'0', ..] | [b'6', b'9', ..] | [b'8', b'9', ..] => 2,
+        [_, _, b'1', ..] | [b'2', b'0', ..] => 3,
+        _ => 4,
+    };
+
+    Some((PhoneLineType::Landline, group(&national, &[area + 1], " ")))
+}
+
+fn french(nsn: &str) -> Option<(PhoneLineType, String)> {
+    if nsn.len() != 9 {
+        return None;
+    }
+    let line_type = match nsn.as_bytes()[0] {
+        b'6' | b'7' => PhoneLineType::Mobile,
+        b'1'..=b'5' | b'9' => PhoneLineType::Landline,
+        b'8' if nsn.starts_with("80") => PhoneLineType::TollFree,
+        _ => return None,
+    };
+
+    Some((line_type, group(&format!("0{}", nsn), &[2, 2, 2, 2], " ")))
+}
+
+fn australian(nsn: &str) -> Option<(PhoneLineType, String)> {
+    // 1800 numbers are dialled without a trunk 0
+    if nsn.len() == 10 && nsn.starts_with("1800") {
+        return Some((PhoneLineType::TollFree, group(nsn, &[4, 3], " ")));
+    }
+    if nsn.len() != 9 {
+        return None;
+    }
+    let national = format!("0{}", nsn);
+    match nsn.as_bytes()[0] {
+        b'4' => Some((PhoneLineType::Mobile, group(&national, &[4, 3], " "))),
+        b'2' | b'3' | b'7' | b'8' => {
+            Some((PhoneLineType::Landline, group(&national, &[2, 4], " ")))
+        }
+        _ => None,
+    }
+}
+
+fn japanese(nsn: &str) -> Option<(PhoneLineType, String)> {
+    let national = format!("0{}", nsn);
+    if nsn.starts_with("70") || nsn.starts_with("80") || nsn.starts_with("90") {
+        return (nsn.len() == 10).then(|| (PhoneLineType::Mobile, group(&national, &[3, 4], "-")));
+    }
+    if nsn.starts_with("120") {
+        return (nsn.len() == 9).then(|| (PhoneLineType::TollFree, group(&national, &[4, 3], "-")));
+    }
+    if nsn.len() != 9 || nsn.starts_with('0') {
+        return None;
+    }
+    // Tokyo and Osaka have one-digit area codes
+    let sizes: &[usize] = match nsn.as_bytes()[0] {
+        b'3' | b'6' => &[2, 4],
+        _ => &[4, 2],
+    };
+
+    Some((PhoneLineType::Landline, group(&national, sizes, "-")))
+}
+
+fn dutch(nsn: &str) -> Option<(PhoneLineType, String)> {
+    let national = format!("0{}", nsn);
+    if nsn.starts_with("800") {
+        return (7..=10)
+            .contains(&nsn.len())
+            .then(|| (PhoneLineType::TollFree, group(&national, &[4], " ")));
+    }
+    if nsn.len() != 9 {
+        return None;
+    }
+    match nsn.as_bytes()[0] {
+        b'6' if (b'1'..=b'5').contains(&nsn.as_bytes()[1]) => {
+            Some((PhoneLineType::Mobile, group(&national, &[2], " ")))
+        }
+        b'1'..=b'5' | b'7' => Some((PhoneLineType::Landline, group(&national, &[3, 3], " "))),
+        _ => None,
+    }
+}
+
+/// (AA) 9XXXX-XXXX for mobiles, (AA) XXXX-XXXX for landlines
+fn brazilian(nsn: &str) -> Option<(PhoneLineType, String)> {
+    if nsn.starts_with("800") {
+        return (nsn.len() == 10).then(|| {
+            (
+                PhoneLineType::TollFree,
+                group(&format!("0{}", nsn), &[4, 3], " "),
+            )
+        });
+    }
+    let bytes = nsn.as_bytes();
+    if bytes.len() < 10 || bytes[0] == b'0' || bytes[1] == b'0' {
+        return None;
+    }
+    let (area, number) = nsn.split_at(2);
+    let line_type = match (number.len(), number.as_bytes()[0]) {
+        (9, b'9') => PhoneLineType::Mobile,
+        (8, b'2'..=b'5') => PhoneLineType::Landline,
+        _ => return None,
+    };
+    let (first, last) = number.split_at(number.len() - 4);
+
+    Some((line_type, format!("({}) {}-{}", area, first, last)))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_phone_numbers() {
+        use PhoneLineType::*;
+
+        let cases = [
+            (
+                "(202) 555-0123",
+                "US",
+                "+12025550123",
+                "(202) 555-0123",
+                Unknown,
+            ),
+            (
+                "1-800-555-0199",
+                "US",
+                "+18005550199",
+                "(800) 555-0199",
+                TollFree,
+            ),
+            (
+                "+1 416 555 0123",
+                "CA",
+                "+14165550123",
+                "(416) 555-0123",
+                Unknown,
+            ),
+            (
+                "020 7946 0958",
+                "GB",
+                "+442079460958",
+                "020 7946 0958",
+                Landline,
+            ),
+            (
+                "+44 (0)7400 123456",
+                "US",
+                "+447400123456",
+                "07400 123456",
+                Mobile,
+            ),
+            (
+                "0151 23456789",
+                "DE",
+                "+4915123456789",
+                "0151 23456789",
+                Mobile,
+            ),
+            ("030 123456", "DE", "+4930123456", "030 123456", Landline),
+            (
+                "0221-1234567",
+                "DE",
+                "+492211234567",
+                "0221 1234567",
+                Landline,
+            ),
+            (
+                "+49 6151 123456",
+                "US",
+                "+496151123456",
+                "06151 123456",
+                Landline,
+            ),
+            (
+                "06 12 34 56 78",
+                "FR",
+                "+33612345678",
+                "06 12 34 56 78",
+                Mobile,
+            ),
+            (
+                "0033 1 42 68 53 00",
+                "US",
+                "+33142685300",
+                "01 42 68 53 00",
+                Landline,
+            ),
+            ("0412 345 678", "AU", "+61412345678", "0412 345 678", Mobile),
+            (
+                "1800 123 456",
+                "AU",
+                "+611800123456",
+                "1800 123 456",
+                TollFree,
+            ),
+            (
+                "090-1234-5678",
+                "JP",
+                "+819012345678",
+                "090-1234-5678",
+                Mobile,
+            ),
+            (
+                "03-1234-5678",
+                "JP",
+                "+81312345678",
+                "03-1234-5678",
+                Landline,
+            ),
+            ("06 12345678", "NL", "+31612345678", "06 12345678", Mobile),
+            (
+                "(11) 91234-5678",
+                "BR",
+                "+5511912345678",
+                "(11) 91234-5678",
+                Mobile,
+            ),
+            (
+                "+55 11 3456 7890",
+                "US",
+                "+551134567890",
+                "(11) 3456-7890",
+                Landline,
+            ),
+            (
+                "+372 5123 4567",
+                "US",
+                "+37251234567",
+                "+37251234567",
+                Unknown,
+            ),
+        ];
+        for (input, country, e164, display, line_type) in cases {
+            let phone = parse_phone(input, country).unwrap_or_else(|| panic!("{}", input));
+            assert_eq!(
+                (
+                    phone.e164.as_deref(),
+                    phone.display.as_str(),
+                    phone.line_type
+                ),
+                (Some(e164), display, line_type),
+                "{}",
+                input
+            );
+        }
+
+        for junk in [
+            "555-CALL-NOW",
+            "12345",
+            "(012) 555-0123",
+            "+44 12",
+            "07000 123456",
+            "0123456789012345678",
+        ] {
+            assert_eq!(parse_phone(junk, "US"), None, "{}", junk);
+        }
+        // Short international numbers are fine where the plan allows them
+        assert_eq!(parse_phone("030 123456", "DE").unwrap().country, "DE");
+        assert_eq!(parse_phone("+1 416 555 0123", "US").unwrap().country, "US");
+
+        // Without a numbering plan, any plausible number of digits
+        let swedish = parse_phone("08-123 456 78", "se").unwrap();
+        assert_eq!(
+            (
+                swedish.country.as_str(),
+                swedish.e164.as_deref(),
+                swedish.display.as_str()
+            ),
+            ("SE", None, "08-123 456 78")
+        );
+        assert_eq!(
+            parse_phone("+46 8 123 456 78", "SE")
+                .unwrap()
+                .e164
+                .as_deref(),
+            Some("+46812345678")
+        );
+        assert_eq!(parse_phone("12345", "SE"), None);
+        assert_eq!(parse_phone("08-CALL-NOW", "SE"), None);
+    }
+}
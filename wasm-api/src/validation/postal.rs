@@ -0,0 +1,148 @@
+/// Check a postal code for a country (ISO 3166 alpha-2, e.g. "CA") and return it
+/// in its usual written form, e.g. "k1a0b1" -> "K1A 0B1". Returns `None` if the
+/// code is not valid for the country.
+///
+/// Countries without specific rules accept 2 to 10 letters and digits, optionally
+/// separated by spaces or hyphens.
+pub fn normalize_postal_code(country: &str, code: &str) -> Option<String> {
+    // Spaces and hyphens are where people put them; the format decides where they go
+    let compact: String = code
+        .chars()
+        .filter(|c| !matches!(c, ' ' | '-'))
+        .collect::<String>()
+        .to_ascii_uppercase();
+    let digits = |s: &str| !s.is_empty() && s.chars().all(|c| c.is_ascii_digit());
+
+    match country.trim().to_ascii_uppercase().as_str() {
+        "US" => match compact.len() {
+            5 if digits(&compact) => Some(compact),
+            9 if digits(&compact) => Some(format!("{}-{}", &compact[..5], &compact[5..])),
+            _ => None,
+        },
+        "CA" => canadian(&compact),
+        "GB" | "UK" => british(&compact),
+        "DE" | "FR" => (compact.len() == 5 && digits(&compact)).then_some(compact),
+        "AU" => (compact.len() == 4 && digits(&compact)).then_some(compact),
+        "JP" => (compact.len() == 7 && digits(&compact))
+            .then(|| format!("{}-{}", &compact[..3], &compact[3..])),
+        "NL" => dutch(&compact),
+        "BR" => (compact.len() == 8 && digits(&compact))
+            .then(|| format!("{}-{}", &compact[..5], &compact[5..])),
+        _ => {
+            let trimmed = code.trim().to_ascii_uppercase();
+            let allowed = trimmed
+                .chars()
+                .all(|c| c.is_ascii_alphanumeric() || matches!(c, ' ' | '-'));
+            (allowed && (2..=10).contains(&compact.len())).then_some(trimmed)
+        }
+    }
+}
+
+/// A1A 1A1. D, F, I, O, Q and U are never used, and W and Z never lead.
+fn canadian(compact: &str) -> Option<String> {
+    let chars: Vec<char> = compact.chars().collect();
+    if chars.len() != 6 {
+        return None;
+    }
+    let letter = |c: char| c.is_ascii_uppercase() && !"DFIOQU".contains(c);
+
+    let valid = chars.iter().enumerate().all(|(i, c)| match i % 2 {
+        0 => letter(*c),
+        _ => c.is_ascii_digit(),
+    });
+    (valid && !matches!(chars[0], 'W' | 'Z'))
+        .then(|| format!("{} {}", &compact[..3], &compact[3..]))
+}
+
+/// An outward code (SW1A, M1, B33, CR2) then an inward code: a digit and two
+/// letters (1AA). The inward code is always the last three characters.
+fn british(compact: &str) -> Option<String> {
+    if compact == "GIR0AA" {
+        return Some("GIR 0AA".to_string());
+    }
+    if !(5..=7).contains(&compact.len()) || !compact.is_ascii() {
+        return None;
+    }
+    let (outward, inward) = compact.split_at(compact.len() - 3);
+
+    let inward_chars: Vec<char> = inward.chars().collect();
+    let inward_valid = inward_chars[0].is_ascii_digit()
+        && inward_chars[1..]
+            .iter()
+            .all(|c| c.is_ascii_uppercase() && !"CIKMOV".contains(*c));
+
+    // Area: one or two letters. District: a digit, then an optional digit or letter.
+    let area_len = outward
+        .chars()
+        .take_while(|c| c.is_ascii_uppercase())
+        .count();
+    let district: Vec<char> = outward.chars().skip(area_len).collect();
+    let outward_valid = (1..=2).contains(&area_len)
+        && (1..=2).contains(&district.len())
+        && district[0].is_ascii_digit()
+        && district
+            .get(1)
+            .is_none_or(|c| c.is_ascii_digit() || c.is_ascii_uppercase());
+
+    (inward_valid && outward_valid).then(|| format!("{} {}", outward, inward))
+}
+
+/// 1234 AB. The number never starts with 0, and SA, SD and SS are not used.
+fn dutch(compact: &str) -> Option<String> {
+    if compact.len() != 6 || !compact.is_ascii() {
+        return None;
+    }
+    let (number, letters) = compact.split_at(4);
+
+    let valid = number.chars().all(|c| c.is_ascii_digit())
+        && !number.starts_with('0')
+        && letters.chars().all(|c| c.is_ascii_uppercase())
+        && !matches!(letters, "SA" | "SD" | "SS");
+    valid.then(|| format!("{} {}", number, letters))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_postal_codes_by_country() {
+        let cases = [
+            ("US", "90210", Some("90210")),
+            ("us", "90210 1234", Some("90210-1234")),
+            ("US", "9021", None),
+            ("CA", "k1a0b1", Some("K1A 0B1")),
+            ("CA", "K1A 0B1", Some("K1A 0B1")),
+            ("CA", "D1A 0B1", None),
+            ("CA", "W1A 0B1", None),
+            ("GB", "sw1a1aa", Some("SW1A 1AA")),
+            ("GB", "M1 1AE", Some("M1 1AE")),
+            ("GB", "B33 8TH", Some("B33 8TH")),
+            ("GB", "CR2 6XH", Some("CR2 6XH")),
+            ("GB", "SW1A 1AI", None),
+            ("GB", "12345", None),
+            ("DE", "10115", Some("10115")),
+            ("FR", "7500", None),
+            ("AU", "2000", Some("2000")),
+            ("JP", "1000001", Some("100-0001")),
+            ("JP", "100-0001", Some("100-0001")),
+            ("NL", "1012ab", Some("1012 AB")),
+            ("NL", "0123 AB", None),
+            ("NL", "1012 SS", None),
+            ("BR", "01310100", Some("01310-100")),
+            ("BR", "01310-10", None),
+            ("SE", " 113 51 ", Some("113 51")),
+            ("SE", "1", None),
+        ];
+
+        for (country, code, expected) in cases {
+            assert_eq!(
+                normalize_postal_code(country, code).as_deref(),
+                expected,
+                "{} {}",
+                country,
+                code
+            );
+        }
+    }
+}